@@ -0,0 +1,70 @@
+//! Compare single-type queries through the per-component-type index against a full scan of
+//! every entity, on a world where only 1% of entities have the queried component. Also compare
+//! `collect_with` on a `{Rare, Common}` filter, which only scans the entities having `Rare`,
+//! against the same query in `new_ordered` mode, which scans every entity.
+//!
+//! Run with `cargo bench --bench component_index`.
+extern crate recs;
+use std::time::{Duration, Instant};
+use recs::*;
+
+struct Rare;
+struct Common;
+
+const ENTITIES: u32 = 100_000;
+const ROUNDS: u32 = 100;
+
+fn populate(ecs: &mut Ecs) {
+    for i in 0..ENTITIES {
+        let id = ecs.create_entity();
+        let _ = ecs.set(id, Common);
+        if i % 100 == 0 {
+            let _ = ecs.set(id, Rare);
+        }
+    }
+}
+
+fn time_filter(ecs: &Ecs) -> (usize, Duration) {
+    let filter = component_filter!(Rare, Common);
+    let mut ids = Vec::new();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        ecs.collect_with(&filter, &mut ids);
+    }
+    (ids.len(), start.elapsed())
+}
+
+fn main() {
+    let mut ecs = Ecs::new();
+    populate(&mut ecs);
+
+    let start = Instant::now();
+    let mut scanned = 0;
+    for _ in 0..ROUNDS {
+        scanned = ecs.iter().filter(|&id| ecs.has::<Rare>(id) == Ok(true)).count();
+    }
+    let scan = start.elapsed();
+
+    let start = Instant::now();
+    let mut indexed = 0;
+    for _ in 0..ROUNDS {
+        indexed = ecs.iter_component::<Rare>().count();
+    }
+    let index = start.elapsed();
+
+    assert_eq!(scanned, indexed);
+    println!("{} entities, {} with the queried component, {} rounds",
+             ENTITIES,
+             indexed,
+             ROUNDS);
+    println!("full scan:      {:?}", scan);
+    println!("indexed query:  {:?}", index);
+
+    let mut ordered = Ecs::new_ordered();
+    populate(&mut ordered);
+    let (all_matched, all_scan) = time_filter(&ordered);
+    let (rarest_matched, rarest_scan) = time_filter(&ecs);
+    assert_eq!(all_matched, rarest_matched);
+    println!("{{Rare, Common}} filter, scanning every entity: {:?}", all_scan);
+    println!("{{Rare, Common}} filter, scanning only Rare:    {:?}", rarest_scan);
+}
@@ -0,0 +1,43 @@
+//! Spawn and destroy entities in waves and check that recycling indices keeps the ID space (and
+//! with it the storage) flat, no matter how many entities have lived and died.
+//!
+//! Run with `cargo bench --bench entity_churn`.
+extern crate recs;
+use std::time::Instant;
+use recs::*;
+
+struct Health(u32);
+
+const LIVE: u32 = 10_000;
+const WAVES: u32 = 100;
+
+fn main() {
+    let mut ecs = Ecs::new();
+    let mut ids = Vec::new();
+    for i in 0..LIVE {
+        let id = ecs.create_entity();
+        let _ = ecs.set(id, Health(i));
+        ids.push(id);
+    }
+    let slots_before = ecs.next_id();
+
+    let start = Instant::now();
+    for _ in 0..WAVES {
+        for id in ids.drain(..) {
+            let _ = ecs.destroy_entity(id);
+        }
+        for i in 0..LIVE {
+            let id = ecs.create_entity();
+            let _ = ecs.set(id, Health(i));
+            ids.push(id);
+        }
+    }
+    let churn = start.elapsed();
+    let slots_after = ecs.next_id();
+
+    assert_eq!(slots_before, slots_after);
+    let total: u64 = ecs.iter_component::<Health>().map(|(_, h)| u64::from(h.0)).sum();
+    assert_eq!(total, u64::from(LIVE) * u64::from(LIVE - 1) / 2);
+    println!("{} waves of {} spawns/destroys: {:?}", WAVES, LIVE, churn);
+    println!("slots used: {} before churn, {} after", slots_before, slots_after);
+}
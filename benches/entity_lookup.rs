@@ -0,0 +1,36 @@
+//! Look up components of random entities, which is dominated by hashing entity IDs, to compare
+//! the default SipHash entity store against the `fast-hash` feature.
+//!
+//! Run with `cargo bench --bench entity_lookup`, then again with `--features fast-hash`.
+extern crate recs;
+use std::time::Instant;
+use recs::*;
+
+struct Health(u32);
+
+const ENTITIES: u32 = 100_000;
+const LOOKUPS: usize = 10_000_000;
+
+fn main() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..ENTITIES).map(|i| ecs.spawn((Health(i),))).collect();
+
+    // a fixed LCG keeps the access pattern random but reproducible
+    let mut state: u64 = 1;
+    let mut total: u64 = 0;
+    let start = Instant::now();
+    for _ in 0..LOOKUPS {
+        state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+        let id = ids[(state >> 33) as usize % ids.len()];
+        total += u64::from(ecs.borrow::<Health>(id).map_or(0, |h| h.0));
+    }
+    let elapsed = start.elapsed();
+
+    let hasher = if cfg!(feature = "fast-hash") { "fast-hash" } else { "SipHash" };
+    println!("{} lookups over {} entities ({}): {:?} (checksum {})",
+             LOOKUPS,
+             ENTITIES,
+             hasher,
+             elapsed,
+             total);
+}
@@ -0,0 +1,55 @@
+use std::any::TypeId;
+use {Component, Ecs, EntityId};
+
+/// A handle for adding components to an entity that is being spawned.
+///
+/// Passed to the closure given to `Ecs::create_with`.
+pub struct EntityBuilder<'a> {
+    ecs: &'a mut Ecs,
+    id: EntityId,
+}
+
+impl<'a> EntityBuilder<'a> {
+    /// Return the ID of the entity being built.
+    pub fn id(&self) -> EntityId {
+        self.id
+    }
+    /// Add a component of type `C` to the entity, replacing any previous one, and return the
+    /// builder for chaining.
+    pub fn set<C: Component>(&mut self, comp: C) -> &mut Self {
+        self.ecs.name_type::<C>();
+        self.ecs
+            .insert_component(self.id, TypeId::of::<C>(), Box::new(comp))
+            .expect("EntityBuilder.set: internal entity error");
+        self
+    }
+    /// Return the system the entity is being built in, e.g. to look up other entities.
+    pub fn ecs(&self) -> &Ecs {
+        self.ecs
+    }
+}
+
+impl Ecs {
+    /// Create a new entity and run `f` to add its components, returning the new ID.
+    ///
+    /// If `f` fails, the partially built entity is destroyed before its error is returned, so
+    /// no half-initialized entity is left behind. Observers registered with `on_destroy` do see
+    /// that entity being destroyed.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `create_entity` if no entity can be created.
+    pub fn create_with<F, E>(&mut self, f: F) -> Result<EntityId, E>
+        where F: FnOnce(&mut EntityBuilder) -> Result<(), E>
+    {
+        let id = self.create_entity();
+        let result = f(&mut EntityBuilder { ecs: self, id });
+        match result {
+            Ok(()) => Ok(id),
+            Err(err) => {
+                self.destroy_entity(id).expect("Ecs.create_with: internal entity error");
+                Err(err)
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use {Component, ComponentMap, Ecs, EcsError, EcsResult, EntityId};
+
+/// A set of components that can be added to an entity in one go, e.g. with `Ecs::spawn`.
+///
+/// Implemented for tuples of up to eight component types, so that
+/// `ecs.spawn((Position(..), Velocity(..)))` creates an entity with both.
+pub trait ComponentBundle {
+    /// Add each of the bundle's components to `map`, and record the name of each of their types
+    /// in `names` for `Ecs::component_type_names`.
+    fn add_to(self, map: &mut ComponentMap, names: &mut HashMap<TypeId, &'static str>);
+    /// Return the types of the bundle's components.
+    fn type_ids() -> Vec<TypeId>;
+}
+
+macro_rules! impl_bundle {
+    ($($T:ident),+) => {
+        impl<$($T: Component),+> ComponentBundle for ($($T,)+) {
+            #[allow(non_snake_case)]
+            fn add_to(self, map: &mut ComponentMap, names: &mut HashMap<TypeId, &'static str>) {
+                let ($($T,)+) = self;
+                $(
+                    names.entry(TypeId::of::<$T>()).or_insert_with(std::any::type_name::<$T>);
+                    map.set($T);
+                )+
+            }
+            fn type_ids() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$T>()),+]
+            }
+        }
+    }
+}
+
+impl_bundle!(A);
+impl_bundle!(A, B);
+impl_bundle!(A, B, C);
+impl_bundle!(A, B, C, D);
+impl_bundle!(A, B, C, D, E);
+impl_bundle!(A, B, C, D, E, F);
+impl_bundle!(A, B, C, D, E, F, G);
+impl_bundle!(A, B, C, D, E, F, G, H);
+
+impl Ecs {
+    /// Create a new entity with each of the components in `bundle`, and return its ID.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `create_entity` if no entity can be created.
+    pub fn spawn<T: ComponentBundle>(&mut self, bundle: T) -> EntityId {
+        let id = self.create_entity();
+        self.insert(id, bundle).expect("Ecs.spawn: internal entity error");
+        id
+    }
+    /// Add each of the components in `bundle` to the specified entity, replacing those it
+    /// already had of the same types.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn insert<T: ComponentBundle>(&mut self, id: EntityId, bundle: T) -> EcsResult<()> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        let mut map = ComponentMap::new();
+        bundle.add_to(&mut map, &mut self.type_names);
+        for (type_id, comp) in map.map {
+            self.insert_component(id, type_id, comp)?;
+        }
+        Ok(())
+    }
+    /// Remove the specified entity's components of each of the types in bundle type `T`, e.g.
+    /// `ecs.remove_bundle::<(Position, Velocity)>(id)`. Types the entity doesn't have are
+    /// ignored.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn remove_bundle<T: ComponentBundle>(&mut self, id: EntityId) -> EcsResult<()> {
+        for type_id in T::type_ids() {
+            self.remove_component(id, &type_id)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use {ComponentFilter, Ecs, EntityId};
+
+/// Results of `collect_with` cached inside the `Ecs`, keyed by the filter's sorted required and
+/// excluded types, with the generation each was computed at.
+pub(crate) type CachedQueries = RefCell<HashMap<(Vec<TypeId>, Vec<TypeId>), (u64, Vec<EntityId>)>>;
+
+/// Cached result of a `collect_with` query, recomputed only after structural changes.
+///
+/// The `Ecs` keeps a counter that is bumped by every structural change (creating or destroying
+/// an entity, setting or removing a component). `QueryCache::get` only re-runs the query when
+/// that counter or the filter has changed since the last call, which is a big saving for worlds
+/// whose composition rarely changes.
+///
+/// A cache should only ever be used with a single `Ecs`.
+#[derive(Default, Debug, Clone)]
+pub struct QueryCache {
+    filter: Option<ComponentFilter>,
+    generation: u64,
+    ids: Vec<EntityId>,
+}
+
+impl QueryCache {
+    /// Create a new, empty query cache.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Return the IDs of all entities in `ecs` matching `filter`, recomputing them only if the
+    /// world changed structurally or a different filter was used since the last call.
+    pub fn get(&mut self, ecs: &Ecs, filter: &ComponentFilter) -> &[EntityId] {
+        let stale = self.generation != ecs.generation ||
+                    self.filter.as_ref() != Some(filter);
+        if stale {
+            ecs.collect_with(filter, &mut self.ids);
+            self.filter = Some(filter.clone());
+            self.generation = ecs.generation;
+        }
+        &self.ids
+    }
+}
+
+impl Ecs {
+    /// Start caching the results of `collect_with` inside the system, so that repeating a query
+    /// with an equal filter returns the stored IDs until the next structural change, without
+    /// the caller managing a `QueryCache`.
+    ///
+    /// One result is kept per distinct filter until `compact` is called, so this suits systems
+    /// that run the same few queries every frame.
+    pub fn enable_query_cache(&mut self) {
+        if self.queries.is_none() {
+            self.queries = Some(Default::default());
+        }
+    }
+    /// Append the IDs matching `filter` to `dest` from the internal cache, if it is enabled,
+    /// recomputing them first if the system changed structurally. Return `false` if the cache
+    /// is disabled.
+    pub(crate) fn append_cached(&self, filter: &ComponentFilter, dest: &mut Vec<EntityId>) -> bool {
+        let queries = match self.queries {
+            Some(ref queries) => queries,
+            None => return false,
+        };
+        let mut required: Vec<_> = filter.iter().collect();
+        let mut excluded: Vec<_> = filter.iter_excluded().collect();
+        required.sort();
+        excluded.sort();
+        let key = (required, excluded);
+        let mut queries = queries.borrow_mut();
+        let stale = queries.get(&key).is_none_or(|&(generation, _)| generation != self.generation);
+        if stale {
+            let ids = self.matching(filter).collect();
+            queries.insert(key.clone(), (self.generation, ids));
+        }
+        dest.extend_from_slice(&queries[&key].1);
+        true
+    }
+}
@@ -0,0 +1,45 @@
+use std::any::{Any, TypeId};
+use {Component, ComponentMap, Ecs, EntityId};
+
+pub(crate) type CleanupFn = fn(&mut dyn Any, EntityId);
+
+/// Trait for component types that hold external resources needing explicit cleanup when they
+/// leave their entity, beyond what `Drop` can do, e.g. notifying the subsystem that owns a GPU
+/// buffer.
+///
+/// Components are opaque to the `Ecs`, so the type must also be registered with
+/// `Ecs::register_cleanup` for the hook to run.
+pub trait DroppableComponent {
+    /// Called when the component is removed from entity `id`, either on its own (e.g. with
+    /// `Ecs::remove`), by `Ecs::clear_components`, or because the entity was destroyed.
+    ///
+    /// Not called for components removed through the map given to `Ecs::with_entity_mut`,
+    /// since the closure takes ownership of those.
+    fn on_remove(&mut self, id: EntityId);
+}
+
+fn cleanup_component<C: Component + DroppableComponent>(comp: &mut dyn Any, id: EntityId) {
+    comp.downcast_mut::<C>()
+        .expect("cleanup_component: internal downcast error")
+        .on_remove(id)
+}
+
+impl Ecs {
+    /// Register component type `C` as having an `on_remove` hook, so that the system calls it
+    /// whenever a `C` is removed from an entity, except through `with_entity_mut`.
+    pub fn register_cleanup<C: Component + DroppableComponent>(&mut self) {
+        self.cleanups.insert(TypeId::of::<C>(), cleanup_component::<C>);
+    }
+    pub(crate) fn cleanup(&self, id: EntityId, type_id: &TypeId, comp: &mut dyn Any) {
+        if let Some(cleanup) = self.cleanups.get(type_id) {
+            cleanup(comp, id);
+        }
+    }
+    pub(crate) fn cleanup_map(&self, id: EntityId, map: &mut ComponentMap) {
+        if !self.cleanups.is_empty() {
+            for (type_id, comp) in &mut map.map {
+                self.cleanup(id, type_id, &mut **comp);
+            }
+        }
+    }
+}
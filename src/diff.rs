@@ -0,0 +1,192 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use {CloneFn, Component, ComponentMap, Ecs, EcsError, EcsResult, EntityId};
+
+pub(crate) type EqFn = fn(&dyn Any, &dyn Any) -> bool;
+
+pub(crate) fn eq_component<C: Component + PartialEq>(a: &dyn Any, b: &dyn Any) -> bool {
+    let a = a.downcast_ref::<C>().expect("eq_component: internal downcast error");
+    let b = b.downcast_ref::<C>().expect("eq_component: internal downcast error");
+    a == b
+}
+
+/// Component changes on a single entity between two worlds.
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
+pub struct EntityDiff {
+    /// Component types the entity has now but didn't have before.
+    pub added: Vec<TypeId>,
+    /// Component types the entity had before but doesn't have now.
+    pub removed: Vec<TypeId>,
+    /// Component types present both before and now whose values differ. Only types registered
+    /// with `Ecs::register_eq` are compared by value.
+    pub changed: Vec<TypeId>,
+}
+
+impl EntityDiff {
+    /// Return `true` if no components were added, removed or changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Changes between two worlds, as produced by `Ecs::diff` and consumed by `Ecs::apply_diff`.
+#[derive(Default, Debug)]
+pub struct WorldDiff {
+    /// Entities that exist now but didn't before.
+    pub added_entities: Vec<EntityId>,
+    /// Entities that existed before but don't now.
+    pub removed_entities: Vec<EntityId>,
+    /// Component changes for every entity that exists now and has any. Entities in
+    /// `added_entities` list all of their components as added.
+    pub entities: HashMap<EntityId, EntityDiff>,
+    values: HashMap<(EntityId, TypeId), (Box<dyn Any>, CloneFn)>,
+}
+
+impl WorldDiff {
+    /// Return `true` if the two worlds had the same entities and components.
+    pub fn is_empty(&self) -> bool {
+        self.added_entities.is_empty() && self.removed_entities.is_empty() &&
+        self.entities.is_empty()
+    }
+}
+
+/// Two worlds are equal if they have the same entity IDs, and each entity has the same
+/// component types in both. Component values are only compared for types registered with
+/// `Ecs::register_eq` on either world; other components only need to be present in both.
+///
+/// Hierarchy links, enabled flags and other bookkeeping are not compared.
+impl PartialEq for Ecs {
+    fn eq(&self, other: &Ecs) -> bool {
+        self.data.len() == other.data.len() &&
+        self.data.iter().all(|(id, map)| {
+            other.data.get(id).is_some_and(|other_map| self.maps_eq(other, map, other_map))
+        })
+    }
+}
+
+impl Ecs {
+    /// Register component type `C` as comparable, so that `diff` can detect changes to its
+    /// value and not just to its presence.
+    pub fn register_eq<C: Component + PartialEq>(&mut self) {
+        self.comparers.insert(TypeId::of::<C>(), eq_component::<C>);
+    }
+    /// Compare this world against a `previous` snapshot of it.
+    ///
+    /// Entity and component presence is always compared. Component values are only compared
+    /// for types registered with `register_eq` on this `Ecs`; for other types, a component
+    /// present in both worlds is assumed unchanged.
+    ///
+    /// The diff also keeps a copy of every added or changed component whose type was
+    /// registered with `register_clone`, so that it can be replayed with `apply_diff`.
+    pub fn diff(&self, previous: &Ecs) -> WorldDiff {
+        let mut diff = WorldDiff::default();
+        for (id, map) in &self.data {
+            let entity = match previous.data.get(id) {
+                Some(prev) => self.diff_maps(map, prev),
+                None => {
+                    diff.added_entities.push(*id);
+                    self.diff_maps(map, &ComponentMap::default())
+                }
+            };
+            for type_id in entity.added.iter().chain(&entity.changed) {
+                if let Some(clone) = self.cloners.get(type_id) {
+                    let value = clone(&*map.map[type_id]);
+                    diff.values.insert((*id, *type_id), (value, *clone));
+                }
+            }
+            if !entity.is_empty() {
+                diff.entities.insert(*id, entity);
+            }
+        }
+        diff.removed_entities
+            .extend(previous.data.keys().filter(|id| !self.data.contains_key(id)));
+        diff
+    }
+    /// Apply the changes described by `diff` to this world.
+    ///
+    /// Added entities are created with their original IDs, removed entities are destroyed, and
+    /// each entity's components are added, removed or replaced. An entity mentioned by the diff
+    /// that doesn't exist here is created. If this world was the `previous` argument used to
+    /// make the diff, it ends up matching the world the diff was made from.
+    ///
+    /// Return `EcsError::ComponentNotFound` without changing anything if the diff is missing the
+    /// value of an added or changed component, i.e. its type wasn't registered with
+    /// `register_clone` on the world that produced the diff. Likewise return
+    /// `EcsError::EntityNotFound` if the diff would create an entity while another generation of
+    /// its index stays alive here.
+    pub fn apply_diff(&mut self, diff: &WorldDiff) -> EcsResult<()> {
+        for (id, entity) in &diff.entities {
+            for type_id in entity.added.iter().chain(&entity.changed) {
+                if !diff.values.contains_key(&(*id, *type_id)) {
+                    return Err(EcsError::ComponentNotFound(*type_id));
+                }
+            }
+        }
+        for id in diff.added_entities.iter().chain(diff.entities.keys()) {
+            match self.occupant(id.index()) {
+                Some(live) if live != *id && !diff.removed_entities.contains(&live) => {
+                    return Err(EcsError::EntityNotFound(*id));
+                }
+                _ => {}
+            }
+        }
+        for id in &diff.removed_entities {
+            if self.exists(*id) {
+                self.destroy_entity(*id)?;
+            }
+        }
+        for id in diff.added_entities.iter().chain(diff.entities.keys()) {
+            self.create_entity_at(*id)?;
+        }
+        for (id, entity) in &diff.entities {
+            for type_id in &entity.removed {
+                self.remove_component(*id, type_id)?;
+            }
+            for type_id in entity.added.iter().chain(&entity.changed) {
+                let (ref value, clone) = diff.values[&(*id, *type_id)];
+                self.insert_component(*id, *type_id, clone(&**value))?;
+            }
+        }
+        Ok(())
+    }
+    /// Return `true` if entity `a` in this world and entity `b` in `other` have the same
+    /// component types, compared the same way as whole worlds with `==`, e.g. to assert on a
+    /// single entity in a test. Return `false` if either entity does not exist.
+    pub fn entity_eq(&self, a: EntityId, other: &Ecs, b: EntityId) -> bool {
+        match (self.data.get(&a), other.data.get(&b)) {
+            (Some(map), Some(other_map)) => self.maps_eq(other, map, other_map),
+            _ => false,
+        }
+    }
+    fn maps_eq(&self, other: &Ecs, map: &ComponentMap, other_map: &ComponentMap) -> bool {
+        map.len() == other_map.len() &&
+        map.map.iter().all(|(type_id, comp)| {
+            match other_map.map.get(type_id) {
+                Some(other_comp) => {
+                    match self.comparers.get(type_id).or_else(|| other.comparers.get(type_id)) {
+                        Some(eq) => eq(&**comp, &**other_comp),
+                        None => true,
+                    }
+                }
+                None => false,
+            }
+        })
+    }
+    fn diff_maps(&self, map: &ComponentMap, prev: &ComponentMap) -> EntityDiff {
+        let mut diff = EntityDiff::default();
+        for (type_id, comp) in &map.map {
+            match prev.map.get(type_id) {
+                Some(old) => {
+                    if let Some(eq) = self.comparers.get(type_id) {
+                        if !eq(&**comp, &**old) {
+                            diff.changed.push(*type_id);
+                        }
+                    }
+                }
+                None => diff.added.push(*type_id),
+            }
+        }
+        diff.removed.extend(prev.map.keys().filter(|t| !map.map.contains_key(t)));
+        diff
+    }
+}
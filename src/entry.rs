@@ -0,0 +1,118 @@
+use std::marker::PhantomData;
+use {Component, Ecs, EcsError, EcsResult, EntityId};
+
+/// A view into a single entity's component of type `C`, which may or may not be present.
+///
+/// Returned by `Ecs::entry`; modeled after `std::collections::hash_map::Entry`.
+pub enum Entry<'a, C: Component> {
+    /// The entity has a component of type `C`.
+    Occupied(OccupiedEntry<'a, C>),
+    /// The entity has no component of type `C`.
+    Vacant(VacantEntry<'a, C>),
+}
+
+/// An entry for a component that is present. Part of the `Entry` enum.
+pub struct OccupiedEntry<'a, C: Component> {
+    ecs: &'a mut Ecs,
+    id: EntityId,
+    _marker: PhantomData<C>,
+}
+
+/// An entry for a component that is absent. Part of the `Entry` enum.
+pub struct VacantEntry<'a, C: Component> {
+    ecs: &'a mut Ecs,
+    id: EntityId,
+    _marker: PhantomData<C>,
+}
+
+impl<'a, C: Component> Entry<'a, C> {
+    /// Return the ID of the entity this entry belongs to.
+    pub fn id(&self) -> EntityId {
+        match *self {
+            Entry::Occupied(ref entry) => entry.id,
+            Entry::Vacant(ref entry) => entry.id,
+        }
+    }
+    /// Insert `default` if the component is absent, then return a mutable reference to it.
+    pub fn or_insert(self, default: C) -> &'a mut C {
+        self.or_insert_with(|| default)
+    }
+    /// Insert the result of `default()` if the component is absent, then return a mutable
+    /// reference to it.
+    pub fn or_insert_with<F: FnOnce() -> C>(self, default: F) -> &'a mut C {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+    /// Run `f` on the component if it is present, then return the entry.
+    pub fn and_modify<F: FnOnce(&mut C)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(ref mut entry) = self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, C: Component> OccupiedEntry<'a, C> {
+    /// Return a shared reference to the component.
+    pub fn get(&self) -> &C {
+        self.ecs.borrow(self.id).expect("OccupiedEntry.get: internal entry error")
+    }
+    /// Return a mutable reference to the component.
+    pub fn get_mut(&mut self) -> &mut C {
+        self.ecs.borrow_mut(self.id).expect("OccupiedEntry.get_mut: internal entry error")
+    }
+    /// Convert the entry into a mutable reference to the component, tied to the `Ecs` borrow.
+    pub fn into_mut(self) -> &'a mut C {
+        self.ecs.borrow_mut(self.id).expect("OccupiedEntry.into_mut: internal entry error")
+    }
+    /// Replace the component, returning the old value.
+    pub fn insert(&mut self, comp: C) -> C {
+        self.ecs
+            .set(self.id, comp)
+            .ok()
+            .and_then(|prev| prev)
+            .expect("OccupiedEntry.insert: internal entry error")
+    }
+    /// Remove the component from the entity, returning it.
+    pub fn remove(self) -> C {
+        self.ecs
+            .remove(self.id)
+            .ok()
+            .and_then(|prev| prev)
+            .expect("OccupiedEntry.remove: internal entry error")
+    }
+}
+
+impl<'a, C: Component> VacantEntry<'a, C> {
+    /// Attach `comp` to the entity, returning a mutable reference to it.
+    pub fn insert(self, comp: C) -> &'a mut C {
+        let _ = self.ecs.set(self.id, comp);
+        self.ecs.borrow_mut(self.id).expect("VacantEntry.insert: internal entry error")
+    }
+}
+
+impl Ecs {
+    /// Return the entry for the specified entity's component of type `C`, for in-place
+    /// inspection, modification or insertion.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn entry<C: Component>(&mut self, id: EntityId) -> EcsResult<Entry<'_, C>> {
+        if self.has::<C>(id)? {
+            Ok(Entry::Occupied(OccupiedEntry {
+                ecs: self,
+                id,
+                _marker: PhantomData,
+            }))
+        } else if self.exists(id) {
+            Ok(Entry::Vacant(VacantEntry {
+                ecs: self,
+                id,
+                _marker: PhantomData,
+            }))
+        } else {
+            Err(EcsError::EntityNotFound(id))
+        }
+    }
+}
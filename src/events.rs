@@ -0,0 +1,49 @@
+use std::any::TypeId;
+use {Books, Ecs, EntityId};
+
+/// A structural change to an `Ecs`, as recorded in its event log.
+///
+/// See `Ecs::enable_event_log`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum EcsEvent {
+    /// An entity was created.
+    EntityCreated(EntityId),
+    /// An entity was destroyed, along with all of its components.
+    EntityDestroyed(EntityId),
+    /// A component of the given type was added to the entity, or replaced.
+    ComponentSet(EntityId, TypeId),
+    /// The entity's component of the given type was removed.
+    ComponentRemoved(EntityId, TypeId),
+}
+
+impl Ecs {
+    /// Start recording every structural change to the system in an event log, which
+    /// `drain_events` hands out in order. This gives replication or analytics layers an exact
+    /// change feed without diffing snapshots.
+    ///
+    /// The operations recorded are those counted by `generation`. Destroying an entity records
+    /// only `EntityDestroyed`, not the removal of each of its components. Logging costs a push
+    /// for every change, so it is off by default.
+    pub fn enable_event_log(&mut self) {
+        if self.events.is_none() {
+            self.events = Some(Vec::new());
+        }
+    }
+    /// Remove and return every event recorded since the last call, oldest first. Return an
+    /// empty vector if `enable_event_log` was never called.
+    pub fn drain_events(&mut self) -> Vec<EcsEvent> {
+        self.events.as_mut().map(::std::mem::take).unwrap_or_default()
+    }
+    pub(crate) fn log_event(&mut self, event: EcsEvent) {
+        self.split().1.log(event);
+    }
+}
+
+impl<'a> Books<'a> {
+    /// Add `event` to the event log if it is enabled.
+    pub(crate) fn log(&mut self, event: EcsEvent) {
+        if let Some(ref mut events) = *self.events {
+            events.push(event);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Hashing for the entity store.
+//!
+//! Entity IDs are handed out by the `Ecs` itself, so they are trusted and don't need the
+//! flooding resistance of the standard SipHash. With the `fast-hash` feature, the entity store
+//! uses a much cheaper multiplicative hash instead.
+
+use std::collections::HashMap;
+use EntityId;
+
+#[cfg(feature = "fast-hash")]
+pub(crate) type EntityState = ::std::hash::BuildHasherDefault<IdHasher>;
+#[cfg(not(feature = "fast-hash"))]
+pub(crate) type EntityState = ::std::collections::hash_map::RandomState;
+
+/// The map type used to store each entity's components.
+pub(crate) type EntityMap<V> = HashMap<EntityId, V, EntityState>;
+
+/// Hasher for entity IDs, in the style of rustc's FxHasher: each word is mixed in with a
+/// rotate, xor and multiply.
+#[cfg(feature = "fast-hash")]
+#[derive(Default, Clone, Copy)]
+pub(crate) struct IdHasher(u64);
+
+#[cfg(feature = "fast-hash")]
+impl ::std::hash::Hasher for IdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(u64::from(*byte));
+        }
+    }
+    fn write_u64(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+use {Ecs, EcsError, EcsResult, EntityId};
+
+impl Ecs {
+    /// Make `parent` the parent of `child`, detaching `child` from its previous parent if it had
+    /// one.
+    ///
+    /// Return `EcsError::EntityNotFound` if either entity does not exist, or `EcsError::Cycle` if
+    /// `child` is `parent` itself or one of its ancestors.
+    pub fn set_parent(&mut self, child: EntityId, parent: EntityId) -> EcsResult<()> {
+        if !self.exists(child) {
+            return Err(EcsError::EntityNotFound(child));
+        }
+        if !self.exists(parent) {
+            return Err(EcsError::EntityNotFound(parent));
+        }
+        let mut ancestor = Some(parent);
+        while let Some(id) = ancestor {
+            if id == child {
+                return Err(EcsError::Cycle(child));
+            }
+            ancestor = self.parents.get(&id).cloned();
+        }
+        self.detach(child);
+        self.parents.insert(child, parent);
+        self.children.entry(parent).or_default().push(child);
+        Ok(())
+    }
+    /// Detach `child` from its parent, returning the previous parent if there was one.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn remove_parent(&mut self, child: EntityId) -> EcsResult<Option<EntityId>> {
+        if !self.exists(child) {
+            return Err(EcsError::EntityNotFound(child));
+        }
+        Ok(self.detach(child))
+    }
+    /// Return the parent of `child`, or `None` if it has no parent.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn parent(&self, child: EntityId) -> EcsResult<Option<EntityId>> {
+        if !self.exists(child) {
+            return Err(EcsError::EntityNotFound(child));
+        }
+        Ok(self.parents.get(&child).cloned())
+    }
+    /// Return the children of `parent`, in the order they were attached.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn children(&self, parent: EntityId) -> EcsResult<Vec<EntityId>> {
+        if !self.exists(parent) {
+            return Err(EcsError::EntityNotFound(parent));
+        }
+        Ok(self.children.get(&parent).cloned().unwrap_or_default())
+    }
+    /// Return an iterator over every descendant of `root` paired with its depth (1 for children,
+    /// 2 for grandchildren and so on), in depth-first order with siblings in the order they were
+    /// attached, e.g. for transform propagation. `root` itself is not yielded.
+    ///
+    /// Each entity is yielded at most once. A root that doesn't exist has no descendants.
+    pub fn iter_hierarchy(&self, root: EntityId) -> impl Iterator<Item = (EntityId, usize)> + '_ {
+        let mut visited = HashSet::new();
+        visited.insert(root);
+        let mut stack = vec![(root, 0)];
+        std::iter::from_fn(move || {
+            let (id, depth) = stack.pop()?;
+            if let Some(children) = self.children.get(&id) {
+                for child in children.iter().rev() {
+                    if visited.insert(*child) {
+                        stack.push((*child, depth + 1));
+                    }
+                }
+            }
+            Some((id, depth))
+        })
+        .skip(1)
+    }
+    /// Destroy the provided entity. If `cascade` is `true`, all of its descendants are destroyed
+    /// too; otherwise its children are orphaned, just like with `destroy_entity`.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist or was already deleted.
+    pub fn destroy_entity_hierarchy(&mut self, id: EntityId, cascade: bool) -> EcsResult<()> {
+        if cascade {
+            for child in self.children(id)? {
+                self.destroy_entity_hierarchy(child, true)?;
+            }
+        }
+        self.destroy_entity(id)
+    }
+    /// Destroy `root` and every entity that is transitively its child according to `is_child`,
+    /// returning the number of entities destroyed.
+    ///
+    /// `is_child(ecs, entity, parent)` should return `true` if `entity` is a child of `parent`.
+    /// This is for hierarchies stored in your own components rather than with `set_parent`. Each
+    /// entity is visited at most once, so cyclic relationships can't cause an infinite loop.
+    ///
+    /// Return `EcsError::EntityNotFound` if `root` does not exist.
+    pub fn destroy_where<F>(&mut self, root: EntityId, is_child: F) -> EcsResult<usize>
+        where F: Fn(&Ecs, EntityId, EntityId) -> bool
+    {
+        if !self.exists(root) {
+            return Err(EcsError::EntityNotFound(root));
+        }
+        let mut doomed = HashSet::new();
+        doomed.insert(root);
+        let mut frontier = vec![root];
+        while let Some(parent) = frontier.pop() {
+            for id in self.iter() {
+                if !doomed.contains(&id) && is_child(self, id, parent) {
+                    doomed.insert(id);
+                    frontier.push(id);
+                }
+            }
+        }
+        for id in &doomed {
+            self.destroy_entity(*id)?;
+        }
+        Ok(doomed.len())
+    }
+    /// Remove every hierarchy link touching `id`: its own parent link and its children's links.
+    pub(crate) fn unlink(&mut self, id: EntityId) {
+        self.detach(id);
+        for child in self.children.remove(&id).unwrap_or_default() {
+            self.parents.remove(&child);
+        }
+    }
+    fn detach(&mut self, child: EntityId) -> Option<EntityId> {
+        let parent = self.parents.remove(&child)?;
+        let now_empty = match self.children.get_mut(&parent) {
+            Some(siblings) => {
+                siblings.retain(|&id| id != child);
+                siblings.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            self.children.remove(&parent);
+        }
+        Some(parent)
+    }
+}
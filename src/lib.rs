@@ -53,32 +53,222 @@
 #![allow(unknown_lints)] // for rust-clippy
 #![warn(missing_docs)]
 use std::any::{TypeId, Any};
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::{self, DefaultHasher};
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::mem;
+use std::rc::Rc;
+
+mod builder;
+mod bundle;
+mod cache;
+mod cleanup;
+mod diff;
+mod entry;
+mod events;
+mod hash;
+mod hierarchy;
+mod prefab;
+mod query;
+mod relation;
+mod remap;
+mod resource;
+mod schedule;
+mod snapshot;
+mod sync;
+mod trait_query;
+mod undo;
+pub use builder::EntityBuilder;
+pub use bundle::ComponentBundle;
+pub use cache::QueryCache;
+pub use cleanup::DroppableComponent;
+pub use diff::{EntityDiff, WorldDiff};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use events::EcsEvent;
+pub use prefab::Prefab;
+pub use query::Query;
+pub use remap::EntityReferencing;
+pub use schedule::{Scheduler, System};
+pub use snapshot::SendableSnapshot;
+pub use sync::SyncEcs;
+pub use undo::Checkpoint;
 
 type IdNumber = u64;
+type PresenceMask = u128;
+type CloneFn = fn(&dyn Any) -> Box<dyn Any>;
+type DestroyFn = Box<dyn FnMut(EntityId)>;
+
+#[derive(Clone, Copy)]
+struct FilterMasks {
+    required: Option<PresenceMask>,
+    excluded: Option<PresenceMask>,
+}
+
+const MAX_PRESENCE_BITS: usize = 128;
+
+const INDEX_BITS: u32 = 32;
+const INDEX_MASK: IdNumber = (1 << INDEX_BITS) - 1;
 
 /// Value type representing an entity in the entity-component system.
 ///
 /// To avoid duplicate entity IDs, these can only be created by calling `Ecs.create_entity()`.
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+///
+/// An ID is made of an *index* and a *generation*. When an entity is destroyed its index is
+/// recycled by a later `create_entity` with the generation bumped, so stale IDs of destroyed
+/// entities never match their slot's new occupant.
+///
+/// IDs are ordered by generation, then index. For entities created by the same `Ecs` before any
+/// index was reused, this is creation order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct EntityId(IdNumber);
 
-/// Error type for ECS results that require a specific entity or component.
+impl EntityId {
+    fn from_index(index: IdNumber, generation: u32) -> Self {
+        EntityId(index | IdNumber::from(generation) << INDEX_BITS)
+    }
+    /// Return the slot index of this ID, which is shared with earlier and later entities that
+    /// occupy the same slot.
+    pub fn index(self) -> u64 {
+        self.0 & INDEX_MASK
+    }
+    /// Return how many times this ID's slot had been reused when the ID was allocated.
+    pub fn generation(self) -> u32 {
+        (self.0 >> INDEX_BITS) as u32
+    }
+}
+
+/// Handle to an entity that may be destroyed while the handle is held, e.g. by UI state.
+///
+/// Because destroyed IDs are never handed out again (their slot is reused with a new
+/// generation), a `WeakEntity` can't accidentally start referring to a different entity.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct WeakEntity(EntityId);
+
+impl WeakEntity {
+    /// Create a weak handle to the entity `id`.
+    pub fn new(id: EntityId) -> Self {
+        WeakEntity(id)
+    }
+    /// Return the entity's ID if it still exists in `ecs`, or `None` if it was destroyed.
+    pub fn upgrade(&self, ecs: &Ecs) -> Option<EntityId> {
+        Some(self.0).filter(|id| ecs.exists(*id))
+    }
+}
+
+/// Whether an entity ID is live, as returned by `Ecs::status`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntityStatus {
+    /// The entity exists.
+    Alive,
+    /// The entity was destroyed. Its slot's latest generation is `current_generation`, which is
+    /// the ID's own generation unless the slot was reused since.
+    Dead {
+        /// The latest generation allocated for the ID's slot.
+        current_generation: u32,
+    },
+    /// The ID was never allocated by this system.
+    NeverExisted,
+}
+
+impl From<EntityId> for WeakEntity {
+    fn from(id: EntityId) -> Self {
+        WeakEntity::new(id)
+    }
+}
+
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.generation() {
+            0 => write!(f, "{}", self.index()),
+            generation => write!(f, "{}v{}", self.index(), generation),
+        }
+    }
+}
+
+/// Error type for fallible ECS operations.
 #[derive(Debug, PartialEq, Eq)]
-pub enum NotFound {
+pub enum EcsError {
     /// A requested entity ID was not present in the system.
-    Entity(EntityId),
+    EntityNotFound(EntityId),
     /// A requested component was not present on an entity.
-    Component(TypeId),
+    ComponentNotFound(TypeId),
+    /// The same component type was requested more than once for simultaneous mutable access,
+    /// which would alias.
+    Aliased(TypeId),
+    /// The same entity was requested more than once for simultaneous mutable access, which
+    /// would alias.
+    AliasedEntity(EntityId),
+    /// A stored component wasn't of the type it was stored under, which means an internal
+    /// invariant was broken. Only the checked `try_*` accessors report this; the others panic.
+    Corruption(TypeId),
+    /// Making the requested parent/child link would make an entity its own ancestor.
+    Cycle(EntityId),
+    /// A boxed component's actual type didn't match the type it was stored under.
+    TypeMismatch {
+        /// The type the component was supposed to have.
+        expected: TypeId,
+        /// The component's actual type.
+        found: TypeId,
+    },
+    /// A counter of the system, such as the supply of entity indices, ran out.
+    Overflow,
+    /// The entity limit set with `Ecs::with_max_entities` was reached.
+    AtCapacity,
+    /// The checkpoint passed to `Ecs::rollback_to` can no longer be rolled back to.
+    InvalidCheckpoint,
 }
 
+/// Former name of `EcsError`, kept so existing signatures still compile. The `Entity` and
+/// `Component` variants are now called `EntityNotFound` and `ComponentNotFound`.
+#[deprecated(since = "3.0.0", note = "renamed to `EcsError`")]
+pub type NotFound = EcsError;
+
+impl fmt::Display for EcsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EcsError::EntityNotFound(id) => write!(f, "entity {} not found", id),
+            EcsError::ComponentNotFound(type_id) => {
+                write!(f, "component {:?} not found on entity", type_id)
+            }
+            EcsError::Aliased(type_id) => {
+                write!(f, "component {:?} requested mutably more than once", type_id)
+            }
+            EcsError::AliasedEntity(id) => {
+                write!(f, "entity {} requested mutably more than once", id)
+            }
+            EcsError::Corruption(type_id) => {
+                write!(f, "component stored as {:?} has a different type", type_id)
+            }
+            EcsError::Cycle(id) => {
+                write!(f, "entity {} would become its own ancestor", id)
+            }
+            EcsError::TypeMismatch { expected, found } => {
+                write!(f, "expected component {:?}, found {:?}", expected, found)
+            }
+            EcsError::Overflow => write!(f, "ECS counter overflowed"),
+            EcsError::AtCapacity => write!(f, "ECS entity limit reached"),
+            EcsError::InvalidCheckpoint => write!(f, "ECS checkpoint is no longer valid"),
+        }
+    }
+}
+
+impl Error for EcsError {}
+
 /// Result type for ECS operations that may fail without a specific entity or component.
-pub type EcsResult<T> = Result<T, NotFound>;
+pub type EcsResult<T> = Result<T, EcsError>;
 
 /// Marker trait for types which can be used as components.
 ///
 /// `Component` is automatically implemented for all eligible types by the
 /// provided `impl`, so you don't have to worry about this. Hooray!
+///
+/// Zero-sized marker components (e.g. `struct Player;`) are cheap: boxing a zero-sized value
+/// never allocates, so they only cost an entry in the entity's component map.
 pub trait Component: Any {}
 impl<T: Any> Component for T {}
 
@@ -87,9 +277,19 @@ impl<T: Any> Component for T {}
 /// The `Ecs` methods `has_all` and `collect_with` each take a `ComponentFilter` instance. The
 /// recommended way to actually create a `ComponentFilter` is with the
 /// [`component_filter!` macro](macro.component_filter!.html).
-#[derive(Default, PartialEq, Eq, Debug, Clone)]
+///
+/// A filter can also list *excluded* types, which matching entities must not have. Use
+/// `exclude` or the [`component_filter_excluding!` macro](macro.component_filter_excluding!.html)
+/// to build one.
+///
+/// The `Debug` output lists type names, e.g. `ComponentFilter { game::Position, !game::Frozen }`,
+/// for types added by name (through `add`, `exclude` or the macros). Types added by `TypeId`
+/// alone are shown as their `TypeId`.
+#[derive(Default, Clone)]
 pub struct ComponentFilter {
     set: HashSet<TypeId>,
+    excluded: HashSet<TypeId>,
+    names: HashMap<TypeId, &'static str>,
 }
 
 impl ComponentFilter {
@@ -100,29 +300,123 @@ impl ComponentFilter {
     /// Add component type `C` to the filter.
     pub fn add<C: Component>(&mut self) {
         self.set.insert(TypeId::of::<C>());
+        self.names.insert(TypeId::of::<C>(), std::any::type_name::<C>());
     }
     /// Remove component type `C` from the filter.
     pub fn remove<C: Component>(&mut self) {
         self.set.remove(&TypeId::of::<C>());
     }
+    /// Add the component type identified by `type_id` to the filter, for types only known at
+    /// runtime.
+    pub fn add_type_id(&mut self, type_id: TypeId) {
+        self.set.insert(type_id);
+    }
+    /// Remove the component type identified by `type_id` from the filter.
+    pub fn remove_type_id(&mut self, type_id: &TypeId) {
+        self.set.remove(type_id);
+    }
     /// Return `true` if the filter already contains component type `C`; otherwise `false`.
     pub fn contains<C: Component>(&mut self) -> bool {
         self.set.contains(&TypeId::of::<C>())
     }
+    /// Exclude component type `C`: entities that have a `C` won't match the filter.
+    pub fn exclude<C: Component>(&mut self) {
+        self.excluded.insert(TypeId::of::<C>());
+        self.names.insert(TypeId::of::<C>(), std::any::type_name::<C>());
+    }
+    /// Stop excluding component type `C`.
+    pub fn unexclude<C: Component>(&mut self) {
+        self.excluded.remove(&TypeId::of::<C>());
+    }
+    /// Return `true` if the filter excludes component type `C`; otherwise `false`.
+    pub fn excludes<C: Component>(&self) -> bool {
+        self.excluded.contains(&TypeId::of::<C>())
+    }
     /// Create a component filter from a vector/slice of `TypeId` instances. (Not recommended;
     /// used by the `component_filter!` macro.)
     pub fn from_slice(slice: &[TypeId]) -> Self {
         let mut this = Self::new();
         for type_id in slice.iter() {
-            this.set.insert(*type_id);
+            this.add_type_id(*type_id);
+        }
+        this
+    }
+    /// Create a component filter from slices of required and excluded `TypeId` instances. (Not
+    /// recommended; used by the `component_filter_excluding!` macro.)
+    pub fn from_slices(required: &[TypeId], excluded: &[TypeId]) -> Self {
+        let mut this = Self::from_slice(required);
+        this.excluded.extend(excluded.iter().cloned());
+        this
+    }
+    /// Like `from_slices`, but with each `TypeId` paired with its type name for the `Debug`
+    /// output. (Not recommended; used by the filter macros.)
+    pub fn from_named_slices(required: &[(TypeId, &'static str)],
+                             excluded: &[(TypeId, &'static str)])
+                             -> Self {
+        let mut this = Self::new();
+        for &(type_id, name) in required {
+            this.set.insert(type_id);
+            this.names.insert(type_id, name);
+        }
+        for &(type_id, name) in excluded {
+            this.excluded.insert(type_id);
+            this.names.insert(type_id, name);
         }
         this
     }
+    /// Return `true` if `map` has every required component type and none of the excluded ones,
+    /// e.g. to filter prefabs or other component bags that aren't part of an `Ecs`.
+    pub fn matches(&self, map: &ComponentMap) -> bool {
+        self.iter().all(|type_id| map.contains_type_id(&type_id)) &&
+        !self.iter_excluded().any(|type_id| map.contains_type_id(&type_id))
+    }
     /// Return an iterator over all the contained component types.
-    #[allow(needless_lifetimes)] // https://github.com/Manishearth/rust-clippy/issues/740
-    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = TypeId> + 'a> {
+    pub fn iter(&self) -> Box<dyn Iterator<Item = TypeId> + '_> {
         Box::new(self.set.iter().cloned())
     }
+    /// Return an iterator over all the excluded component types.
+    pub fn iter_excluded(&self) -> Box<dyn Iterator<Item = TypeId> + '_> {
+        Box::new(self.excluded.iter().cloned())
+    }
+}
+
+impl PartialEq for ComponentFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.set == other.set && self.excluded == other.excluded
+    }
+}
+
+impl Eq for ComponentFilter {}
+
+impl fmt::Debug for ComponentFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |type_id: &TypeId| match self.names.get(type_id) {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", type_id),
+        };
+        let mut required: Vec<_> = self.set.iter().map(name).collect();
+        let mut excluded: Vec<_> = self.excluded.iter().map(|t| format!("!{}", name(t))).collect();
+        required.sort();
+        excluded.sort();
+        required.extend(excluded);
+        if required.is_empty() {
+            write!(f, "ComponentFilter {{}}")
+        } else {
+            write!(f, "ComponentFilter {{ {} }}", required.join(", "))
+        }
+    }
+}
+
+impl FromIterator<TypeId> for ComponentFilter {
+    fn from_iter<I: IntoIterator<Item = TypeId>>(iter: I) -> Self {
+        ComponentFilter { set: iter.into_iter().collect(), ..Default::default() }
+    }
+}
+
+impl Extend<TypeId> for ComponentFilter {
+    fn extend<I: IntoIterator<Item = TypeId>>(&mut self, iter: I) {
+        self.set.extend(iter)
+    }
 }
 
 /// Create a `ComponentFilter` by type name.
@@ -152,13 +446,135 @@ impl ComponentFilter {
 #[macro_export]
 macro_rules! component_filter {
   ($($x:ty),*) => (
-    $crate::ComponentFilter::from_slice(
-      &vec![$(std::any::TypeId::of::<$x>()),*]
+    $crate::ComponentFilter::from_named_slices(
+      &[$((::std::any::TypeId::of::<$x>(), ::std::any::type_name::<$x>())),*],
+      &[]
     )
   );
   ($($x:ty,)*) => (component_filter![$($x),*])
 }
 
+macro_rules! borrow_mut_tuple {
+    ($(#[$attr:meta])* fn $name:ident<$($T:ident),+>) => {
+        $(#[$attr])*
+        pub fn $name<$($T: Component),+>(&mut self, id: EntityId) -> EcsResult<($(&mut $T),+)> {
+            let map = self.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+            let mut comps = map.borrow_disjoint_mut(&[$(TypeId::of::<$T>()),+])?.into_iter();
+            Ok(($(comps.next()
+                       .and_then(|c| c.downcast_mut::<$T>())
+                       .expect(concat!("Ecs.", stringify!($name), ": internal downcast error"))),+))
+        }
+    }
+}
+
+/// A component's value before and after a write, as returned by `Ecs::set_logged`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ChangeRecord<C> {
+    /// The component the entity had before, if any.
+    pub old: Option<C>,
+    /// The component the entity has now.
+    pub new: C,
+}
+
+/// Statistics about a single query, as returned by `Ecs::collect_with_stats`.
+#[derive(Default, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct QueryStats {
+    /// Number of entities that were tested against the filter.
+    pub scanned: usize,
+    /// Number of entities that matched the filter.
+    pub matched: usize,
+}
+
+impl QueryStats {
+    /// Return the fraction of scanned entities that matched, between `0.0` and `1.0`. Return
+    /// `0.0` if nothing was scanned.
+    pub fn selectivity(&self) -> f64 {
+        if self.scanned == 0 {
+            0.0
+        } else {
+            self.matched as f64 / self.scanned as f64
+        }
+    }
+}
+
+/// Create a `ComponentFilter` with both required and excluded types, by type name.
+///
+/// Required types come first, then a semicolon, then excluded types. If you want all entities
+/// with components `Alive` and `Position` but without `Frozen`:
+///
+/// ```
+/// #[macro_use]
+/// extern crate recs;
+/// use recs::Ecs;
+///
+/// struct Alive;
+/// struct Position;
+/// struct Frozen;
+///
+/// fn main() {
+///     let mut sys = Ecs::new();
+///     let walker = sys.create_entity();
+///     let statue = sys.create_entity();
+///     let _ = sys.set(walker, Alive);
+///     let _ = sys.set(walker, Position);
+///     let _ = sys.set(statue, Alive);
+///     let _ = sys.set(statue, Position);
+///     let _ = sys.set(statue, Frozen);
+///     let mut ids = Vec::new();
+///     let filter = component_filter_excluding!(Alive, Position; Frozen);
+///     sys.collect_with(&filter, &mut ids);
+///     assert_eq!(ids, vec![walker]);
+/// }
+/// ```
+#[macro_export]
+macro_rules! component_filter_excluding {
+  ($($x:ty),* ; $($y:ty),*) => (
+    $crate::ComponentFilter::from_named_slices(
+      &[$((::std::any::TypeId::of::<$x>(), ::std::any::type_name::<$x>())),*],
+      &[$((::std::any::TypeId::of::<$y>(), ::std::any::type_name::<$y>())),*]
+    )
+  );
+}
+
+/// Register several component types with an `Ecs` in one place.
+///
+/// `components!(ecs; A, B)` calls `register_clone` for each listed type, so that cloning the
+/// `Ecs` and recording diffs work for all of them. `components!(ecs, eq; A, B)` additionally
+/// calls `register_eq`, so `diff` and `==` compare their values. Keeping every type in one list
+/// avoids forgetting to register one of them.
+///
+/// ```
+/// #[macro_use]
+/// extern crate recs;
+/// use recs::Ecs;
+///
+/// #[derive(Clone, PartialEq)]
+/// struct Position(i32, i32);
+/// #[derive(Clone, PartialEq)]
+/// struct Health(u32);
+///
+/// fn main() {
+///     let mut ecs = Ecs::new();
+///     components!(ecs, eq; Position, Health);
+///     let id = ecs.create_entity();
+///     let _ = ecs.set(id, Position(0, 0));
+///     let _ = ecs.set(id, Health(10));
+///     let copy = ecs.clone();
+///     assert!(copy == ecs);
+/// }
+/// ```
+#[macro_export]
+macro_rules! components {
+  ($ecs:expr, eq; $($x:ty),*) => ({
+    let ecs: &mut $crate::Ecs = &mut $ecs;
+    $(ecs.register_clone::<$x>(); ecs.register_eq::<$x>();)*
+  });
+  ($ecs:expr; $($x:ty),*) => ({
+    let ecs: &mut $crate::Ecs = &mut $ecs;
+    $(ecs.register_clone::<$x>();)*
+  });
+}
+
 /// Primary data structure containing entity and component data.
 ///
 /// Notice that `Ecs` itself has no type parameters. Its methods to interact
@@ -169,51 +585,292 @@ macro_rules! component_filter {
 /// Tip: using `#[derive(Clone)]` on your component types will make your life a little easier by
 /// enabling the `get` method, which avoids locking down the `Ecs` with a mutable or immutable
 /// borrow.
+///
+/// `Ecs` implements `Clone`, producing a faithful copy with the same entity IDs and ID counter.
+/// Because components are stored as `Any`, every component type present in the system must
+/// first be registered with `register_clone`; cloning panics if an unregistered type is found.
+///
+/// The order in which entities are visited by `iter`, `collect`, `collect_with` and friends is
+/// unspecified and may differ between runs. Create the system with `Ecs::new_ordered` if it
+/// must be deterministic, e.g. for lockstep simulations.
 #[derive(Default)]
 pub struct Ecs {
     ids: IdNumber,
-    data: HashMap<EntityId, ComponentMap>,
+    data: hash::EntityMap<ComponentMap>,
+    index: HashMap<TypeId, HashSet<EntityId>>,
+    bits: HashMap<TypeId, u32>,
+    parents: HashMap<EntityId, EntityId>,
+    children: HashMap<EntityId, Vec<EntityId>>,
+    cloners: HashMap<TypeId, CloneFn>,
+    comparers: HashMap<TypeId, diff::EqFn>,
+    generation: u64,
+    remappers: HashMap<TypeId, remap::RemapFn>,
+    trait_casts: HashMap<TypeId, Rc<dyn Any>>,
+    disabled: HashSet<EntityId>,
+    free: Vec<EntityId>,
+    type_names: HashMap<TypeId, &'static str>,
+    destroy_observers: Vec<DestroyFn>,
+    created: HashMap<EntityId, u64>,
+    max_entities: Option<usize>,
+    order: Option<Vec<EntityId>>,
+    slots: HashMap<IdNumber, u32>,
+    occupants: HashMap<IdNumber, u32>,
+    tick: u64,
+    stamps: Option<HashMap<(EntityId, TypeId), u64>>,
+    userdata: HashMap<EntityId, u64>,
+    senders: HashMap<TypeId, snapshot::SendFn>,
+    cleanups: HashMap<TypeId, cleanup::CleanupFn>,
+    resources: ComponentMap,
+    queries: Option<cache::CachedQueries>,
+    events: Option<Vec<EcsEvent>>,
+    journal: Option<undo::Journal>,
+    relations: relation::Relations,
+}
+
+impl Clone for Ecs {
+    fn clone(&self) -> Self {
+        let data = self.data
+            .iter()
+            .map(|(id, map)| (*id, map.clone_with(&self.cloners)))
+            .collect();
+        Ecs {
+            ids: self.ids,
+            data,
+            index: self.index.clone(),
+            bits: self.bits.clone(),
+            parents: self.parents.clone(),
+            children: self.children.clone(),
+            cloners: self.cloners.clone(),
+            comparers: self.comparers.clone(),
+            generation: self.generation,
+            remappers: self.remappers.clone(),
+            trait_casts: self.trait_casts.clone(),
+            disabled: self.disabled.clone(),
+            free: self.free.clone(),
+            type_names: self.type_names.clone(),
+            destroy_observers: Vec::new(),
+            created: self.created.clone(),
+            max_entities: self.max_entities,
+            order: self.order.clone(),
+            slots: self.slots.clone(),
+            occupants: self.occupants.clone(),
+            tick: self.tick,
+            stamps: self.stamps.clone(),
+            userdata: self.userdata.clone(),
+            senders: self.senders.clone(),
+            cleanups: self.cleanups.clone(),
+            resources: self.resources.clone_with(&self.cloners),
+            queries: self.queries.as_ref().map(|_| Default::default()),
+            events: self.events.clone(),
+            journal: self.journal.as_ref().map(|journal| journal.clone_with(&self.cloners)),
+            relations: self.relations.clone(),
+        }
+    }
+}
+
+impl FromIterator<(EntityId, ComponentMap)> for Ecs {
+    /// Rebuild an ECS from entity-component pairs. New entities are numbered after the highest
+    /// provided ID; use `Ecs::from_parts` to choose the counter explicitly.
+    ///
+    /// Panics like `Ecs::from_parts` if two different generations of the same index are
+    /// provided.
+    fn from_iter<I: IntoIterator<Item = (EntityId, ComponentMap)>>(iter: I) -> Self {
+        let entries: Vec<_> = iter.into_iter().collect();
+        let next_id = entries.iter().map(|&(id, _)| id.index() + 1).max().unwrap_or(0);
+        Ecs::from_parts(entries, next_id)
+    }
+}
+
+/// Consume the ECS, yielding every entity's ID and owned components, e.g. to migrate the data
+/// into another structure. Unlike `Ecs::drain`, the system itself is gone afterwards.
+impl IntoIterator for Ecs {
+    type Item = (EntityId, ComponentMap);
+    type IntoIter = std::vec::IntoIter<(EntityId, ComponentMap)>;
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.drain().collect::<Vec<_>>().into_iter()
+    }
+}
+
+fn downcast_box<C: Component>(comp: Box<dyn Any>, context: &str) -> C {
+    *comp.downcast::<C>().unwrap_or_else(|_| panic!("{}: internal downcast error", context))
 }
 
+fn clone_component<C: Component + Clone>(comp: &dyn Any) -> Box<dyn Any> {
+    Box::new(comp.downcast_ref::<C>().expect("clone_component: internal downcast error").clone())
+}
+
+/// The set of components attached to a single entity.
+///
+/// Every entity in an `Ecs` owns a `ComponentMap`, but it is also useful on its own as a typed
+/// bag of components that isn't tied to any entity, e.g. as a template for spawning.
 #[derive(Default)]
-struct ComponentMap {
-    map: HashMap<TypeId, Box<Any>>,
+pub struct ComponentMap {
+    map: HashMap<TypeId, Box<dyn Any>>,
+    mask: PresenceMask,
+    /// Cached result of `Ecs::signature`, cleared whenever a component is added or removed.
+    signature: Cell<Option<u64>>,
+    /// Type names of the components added with `set`, handed to the `Ecs` for
+    /// `component_type_names` when the map is stored or changed through `with_entity_mut`.
+    names: HashMap<TypeId, &'static str>,
 }
 
 impl ComponentMap {
-    fn set<C: Component>(&mut self, component: C) -> Option<C> {
+    /// Create a new and empty component map.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Add a component of type `C` to the map, returning the previous one if there was one.
+    pub fn set<C: Component>(&mut self, component: C) -> Option<C> {
+        self.signature.set(None);
+        self.names.insert(TypeId::of::<C>(), std::any::type_name::<C>());
         self.map
             .insert(TypeId::of::<C>(), Box::new(component))
             .map(|old| *old.downcast::<C>().expect("ComponentMap.set: internal downcast error"))
     }
-    fn borrow<C: Component>(&self) -> EcsResult<&C> {
+    /// Return a shared reference to the component of type `C`, or `EcsError::ComponentNotFound` if
+    /// the map doesn't have one.
+    pub fn borrow<C: Component>(&self) -> EcsResult<&C> {
         self.map
             .get(&TypeId::of::<C>())
             .map(|c| {
                 c.downcast_ref()
                  .expect("ComponentMap.borrow: internal downcast error")
             })
-            .ok_or_else(|| NotFound::Component(TypeId::of::<C>()))
+            .ok_or_else(|| EcsError::ComponentNotFound(TypeId::of::<C>()))
     }
-    #[allow(map_clone)]
-    fn get<C: Component + Clone>(&self) -> EcsResult<C> {
+    /// Return a clone of the component of type `C`, or `EcsError::ComponentNotFound` if the map
+    /// doesn't have one.
+    pub fn get<C: Component + Clone>(&self) -> EcsResult<C> {
         self.borrow::<C>()
-            .map(Clone::clone)
+            .cloned()
     }
-    fn contains_type_id(&self, id: &TypeId) -> bool {
+    /// Return `true` if the map has a component of the type identified by `id`.
+    pub fn contains_type_id(&self, id: &TypeId) -> bool {
         self.map.contains_key(id)
     }
-    fn contains<C: Component>(&self) -> bool {
+    /// Return `true` if the map has a component of type `C`.
+    pub fn contains<C: Component>(&self) -> bool {
         self.contains_type_id(&TypeId::of::<C>())
     }
-    fn borrow_mut<C: Component>(&mut self) -> EcsResult<&mut C> {
+    /// Return a mutable reference to the component of type `C`, or `EcsError::ComponentNotFound` if
+    /// the map doesn't have one.
+    pub fn borrow_mut<C: Component>(&mut self) -> EcsResult<&mut C> {
         match self.map.get_mut(&TypeId::of::<C>()) {
             Some(c) => {
                 Ok(c.downcast_mut()
                     .expect("ComponentMap.borrow_mut: internal downcast error"))
             }
-            None => Err(NotFound::Component(TypeId::of::<C>())),
+            None => Err(EcsError::ComponentNotFound(TypeId::of::<C>())),
+        }
+    }
+    /// Remove the component of type `C` from the map, returning it if there was one.
+    pub fn remove<C: Component>(&mut self) -> Option<C> {
+        self.signature.set(None);
+        self.map
+            .remove(&TypeId::of::<C>())
+            .map(|old| *old.downcast::<C>().expect("ComponentMap.remove: internal downcast error"))
+    }
+    /// Like `set`, but return `EcsError::Corruption` instead of panicking if the replaced
+    /// component wasn't actually a `C`.
+    pub fn try_set<C: Component>(&mut self, component: C) -> EcsResult<Option<C>> {
+        let type_id = TypeId::of::<C>();
+        self.signature.set(None);
+        self.names.insert(type_id, std::any::type_name::<C>());
+        match self.map.insert(type_id, Box::new(component)) {
+            Some(old) => {
+                old.downcast().map(|old| Some(*old)).map_err(|_| EcsError::Corruption(type_id))
+            }
+            None => Ok(None),
+        }
+    }
+    /// Like `borrow`, but return `EcsError::Corruption` instead of panicking if the stored
+    /// component isn't actually a `C`.
+    pub fn try_borrow<C: Component>(&self) -> EcsResult<&C> {
+        let type_id = TypeId::of::<C>();
+        let comp = self.map.get(&type_id).ok_or(EcsError::ComponentNotFound(type_id))?;
+        comp.downcast_ref().ok_or(EcsError::Corruption(type_id))
+    }
+    /// Like `get`, but return `EcsError::Corruption` instead of panicking if the stored component
+    /// isn't actually a `C`.
+    pub fn try_get<C: Component + Clone>(&self) -> EcsResult<C> {
+        self.try_borrow::<C>().cloned()
+    }
+    /// Like `borrow_mut`, but return `EcsError::Corruption` instead of panicking if the stored
+    /// component isn't actually a `C`.
+    pub fn try_borrow_mut<C: Component>(&mut self) -> EcsResult<&mut C> {
+        let type_id = TypeId::of::<C>();
+        let comp = self.map.get_mut(&type_id).ok_or(EcsError::ComponentNotFound(type_id))?;
+        comp.downcast_mut().ok_or(EcsError::Corruption(type_id))
+    }
+    /// Like `remove`, but return `EcsError::Corruption` instead of panicking if the stored
+    /// component isn't actually a `C`, in which case it is left in the map.
+    pub fn try_remove<C: Component>(&mut self) -> EcsResult<Option<C>> {
+        let type_id = TypeId::of::<C>();
+        match self.map.get(&type_id) {
+            Some(comp) if !comp.is::<C>() => Err(EcsError::Corruption(type_id)),
+            _ => Ok(self.remove()),
+        }
+    }
+    /// Return the number of components in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    /// Return `true` if the map has no components.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    /// Return an iterator over the types of all the components in the map.
+    pub fn type_ids(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.map.keys().cloned()
+    }
+    fn borrow_disjoint_mut(&mut self, types: &[TypeId]) -> EcsResult<Vec<&mut Box<dyn Any>>> {
+        for (i, type_id) in types.iter().enumerate() {
+            if types[..i].contains(type_id) {
+                return Err(EcsError::Aliased(*type_id));
+            }
+        }
+        let mut slots: Vec<Option<&mut Box<dyn Any>>> = types.iter().map(|_| None).collect();
+        for (type_id, comp) in &mut self.map {
+            if let Some(i) = types.iter().position(|t| t == type_id) {
+                slots[i] = Some(comp);
+            }
         }
+        slots.into_iter()
+             .zip(types)
+             .map(|(slot, type_id)| slot.ok_or(EcsError::ComponentNotFound(*type_id)))
+             .collect()
+    }
+    fn clone_with(&self, cloners: &HashMap<TypeId, CloneFn>) -> ComponentMap {
+        let map = self.map
+            .iter()
+            .map(|(type_id, comp)| {
+                let clone = cloners.get(type_id).unwrap_or_else(|| {
+                    panic!("Ecs.clone: component type {:?} was not registered with \
+                            Ecs.register_clone",
+                           type_id)
+                });
+                (*type_id, clone(&**comp))
+            })
+            .collect();
+        ComponentMap {
+            map,
+            mask: self.mask,
+            signature: self.signature.clone(),
+            names: self.names.clone(),
+        }
+    }
+    /// Return a hash of the map's sorted component types, computing it only if it isn't cached.
+    fn signature(&self) -> u64 {
+        if let Some(signature) = self.signature.get() {
+            return signature;
+        }
+        let mut types: Vec<_> = self.type_ids().collect();
+        types.sort();
+        let mut hasher = DefaultHasher::new();
+        types.hash(&mut hasher);
+        let signature = hasher.finish();
+        self.signature.set(Some(signature));
+        signature
     }
 }
 
@@ -222,12 +879,291 @@ impl Ecs {
     pub fn new() -> Self {
         Default::default()
     }
+    /// Create a new and empty ECS whose entity IDs are allocated counting up from `start`.
+    ///
+    /// This is useful for running several `Ecs` shards whose IDs should never overlap, e.g. so
+    /// their entities can later be merged without collisions. The caller is responsible for
+    /// choosing ranges that don't overlap; a shard allocating past the start of the next one
+    /// will happily reuse its IDs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` is not below `2^32`, since indices are 32 bits wide.
+    pub fn with_id_offset(start: u64) -> Self {
+        assert!(start <= INDEX_MASK, "Ecs.with_id_offset: start must be below 2^32");
+        Ecs { ids: start, ..Default::default() }
+    }
+    /// Create a new and empty ECS that visits entities in creation order.
+    ///
+    /// `iter`, `iter_entities`, `collect`, `collect_with`, `find`, `find_all` and `drain` then
+    /// yield entities in the order they were created, which two systems fed the same commands
+    /// always agree on. Single-type iterators backed by the per-type index, like `with` and
+    /// `iter_component`, stay unordered. Destroying an entity takes time linear in the number of
+    /// entities in this mode.
+    pub fn new_ordered() -> Self {
+        Ecs { order: Some(Vec::new()), ..Default::default() }
+    }
+    /// Create a new and empty ECS that holds at most `max` live entities at a time, e.g. to keep
+    /// untrusted scripts from spawning without bound.
+    ///
+    /// Use `try_create_entity` to get an `EcsError::AtCapacity` error instead of a panic when
+    /// the limit is reached. Destroying entities frees capacity. Entities recreated by
+    /// `apply_diff` don't count against the limit.
+    pub fn with_max_entities(max: usize) -> Self {
+        Ecs { max_entities: Some(max), ..Default::default() }
+    }
+    /// Rebuild an ECS from entity-component pairs, e.g. the output of `drain` or a custom
+    /// persistence layer. Entities created afterwards are numbered from `next_id`.
+    ///
+    /// If an ID appears more than once, the last map wins.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index of any of the provided IDs is greater than or equal to `next_id`,
+    /// since later allocations would collide with it, or if two different generations of the
+    /// same index are provided.
+    pub fn from_parts<I>(entries: I, next_id: u64) -> Self
+        where I: IntoIterator<Item = (EntityId, ComponentMap)>
+    {
+        let mut ecs = Ecs::new();
+        for (id, map) in entries {
+            assert!(id.index() < next_id,
+                    "Ecs.from_parts: entity {} is not below next_id {}",
+                    id,
+                    next_id);
+            if ecs.create_entity_at(id).is_err() {
+                panic!("Ecs.from_parts: entity {} shares its index with another entity", id);
+            }
+            ecs.replace_map(id, map);
+        }
+        ecs.ids = next_id;
+        ecs
+    }
+    /// Register component type `C` as cloneable, so that cloning the `Ecs` can copy it.
+    ///
+    /// Every component type present in the system when it is cloned must be registered.
+    pub fn register_clone<C: Component + Clone>(&mut self) {
+        self.cloners.insert(TypeId::of::<C>(), clone_component::<C>);
+        self.name_type::<C>();
+    }
+    /// Make sure the entity with the exact ID `id` exists, creating it without components if it
+    /// doesn't, and return its components. This is for reconstructing a saved world with its
+    /// original IDs.
+    ///
+    /// The ID counter is moved past the index of `id`, so entities created afterwards never
+    /// collide with it; unused indices below it are skipped rather than handed out later. The
+    /// new entity doesn't count against the limit set with `with_max_entities`. The returned map
+    /// is read-only so the system's indices stay consistent; add components with `set`.
+    ///
+    /// Return `EcsError::EntityNotFound` if another generation of the same index is alive, since
+    /// two live entities can't share an index.
+    pub fn get_or_create_at(&mut self, id: EntityId) -> EcsResult<&ComponentMap> {
+        self.create_entity_at(id)?;
+        Ok(&self.data[&id])
+    }
     /// Create a new entity in the ECS without components and return its ID.
+    ///
+    /// The index of a destroyed entity is reused if one is free, so the ID space stays bounded
+    /// by the peak number of live entities.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit set with `with_max_entities` was reached, or if the system ran out of
+    /// entity indices. See `try_create_entity` for a version that doesn't panic.
     pub fn create_entity(&mut self) -> EntityId {
-        let new_id = EntityId(self.ids);
-        self.ids += 1;
+        self.try_create_entity().unwrap_or_else(|err| panic!("Ecs.create_entity: {}", err))
+    }
+    /// Like `create_entity`, but return `EcsError::AtCapacity` if the limit set with
+    /// `with_max_entities` was reached, or `EcsError::Overflow` if the system ran out of entity
+    /// indices.
+    pub fn try_create_entity(&mut self) -> EcsResult<EntityId> {
+        if self.max_entities.is_some_and(|max| self.data.len() >= max) {
+            return Err(EcsError::AtCapacity);
+        }
+        let new_id = self.allocate_id()?;
+        let slot = self.slots.entry(new_id.index()).or_insert(0);
+        *slot = (*slot).max(new_id.generation());
+        self.occupants.insert(new_id.index(), new_id.generation());
         self.data.insert(new_id, Default::default());
-        new_id
+        if let Some(ref mut order) = self.order {
+            order.push(new_id);
+        }
+        self.generation += 1;
+        self.created.insert(new_id, self.generation);
+        self.log_event(EcsEvent::EntityCreated(new_id));
+        self.record(EcsEvent::EntityCreated(new_id), None);
+        Ok(new_id)
+    }
+    /// Create a new entity for each of the provided component maps, e.g. a batch of
+    /// deserialized prefab instances, and return the new IDs in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `create_entity` if an entity can't be created.
+    pub fn extend<I: IntoIterator<Item = ComponentMap>>(&mut self, entities: I) -> Vec<EntityId> {
+        entities.into_iter()
+            .map(|map| {
+                let id = self.create_entity();
+                self.replace_map(id, map);
+                id
+            })
+            .collect()
+    }
+    /// Register `f` to be called with the ID of every entity that is destroyed, e.g. to keep a
+    /// spatial index or network replica in sync.
+    ///
+    /// Observers run in registration order from `destroy_entity` (and everything built on it,
+    /// like `destroy_entity_hierarchy`) and `drain`, while the entity still exists. They are not
+    /// carried over when the `Ecs` is cloned.
+    pub fn on_destroy<F: FnMut(EntityId) + 'static>(&mut self, f: F) {
+        self.destroy_observers.push(Box::new(f));
+    }
+    /// Enable or disable the specified entity. Entities are enabled when created.
+    ///
+    /// Disabled entities keep all their components but are skipped by the enabled-only queries
+    /// such as `collect_enabled_with`, which makes it cheap to temporarily exclude them from
+    /// systems. All other methods, including `collect_with`, still see them.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn set_enabled(&mut self, id: EntityId, enabled: bool) -> EcsResult<()> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        if enabled {
+            self.disabled.remove(&id);
+        } else {
+            self.disabled.insert(id);
+        }
+        Ok(())
+    }
+    /// Return `true` if the specified entity is enabled, or `EcsError::EntityNotFound` if the
+    /// entity does not exist.
+    pub fn is_enabled(&self, id: EntityId) -> EcsResult<bool> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        Ok(!self.disabled.contains(&id))
+    }
+    /// Return the index that the next fresh (not recycled) ID allocated by `create_entity` will
+    /// have.
+    ///
+    /// Every entity created so far has a smaller index, so this can be used to checkpoint the
+    /// allocator or to tell how many slots have ever been used (offset by the starting number
+    /// given to `with_id_offset`, if any).
+    pub fn next_id(&self) -> u64 {
+        self.ids
+    }
+    /// Attach an untyped `u64` tag to the specified entity, replacing any previous one, e.g. to
+    /// correlate it with a network ID without defining a component for it.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn set_userdata(&mut self, id: EntityId, data: u64) -> EcsResult<()> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        if data == 0 {
+            self.userdata.remove(&id);
+        } else {
+            self.userdata.insert(id, data);
+        }
+        Ok(())
+    }
+    /// Return the tag attached to the specified entity with `set_userdata`, or 0 if it was never
+    /// set.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn userdata(&self, id: EntityId) -> EcsResult<u64> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        Ok(self.userdata.get(&id).cloned().unwrap_or(0))
+    }
+    /// Start recording the tick at which each component is added, so that `component_age` can
+    /// report it. Components already present count as added at the current tick.
+    ///
+    /// Tracking costs a map insertion for every component added, so it is off by default.
+    pub fn enable_component_ticks(&mut self) {
+        if self.stamps.is_none() {
+            let tick = self.tick;
+            let stamps = self.data
+                .iter()
+                .flat_map(|(id, map)| map.type_ids().map(move |type_id| ((*id, type_id), tick)))
+                .collect();
+            self.stamps = Some(stamps);
+        }
+    }
+    /// Advance the system's tick counter by one and return the new tick. The ECS never advances
+    /// it on its own; a game would typically call this once per frame or turn.
+    pub fn advance_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+    /// Return the current value of the tick counter advanced by `advance_tick`.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+    /// Return how many ticks ago the entity's component of the type identified by `type_id` was
+    /// added (or last replaced with `set`), e.g. to expire buffs older than some duration.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist, or
+    /// `EcsError::ComponentNotFound` if it lacks that component or `enable_component_ticks` was
+    /// never called.
+    pub fn component_age(&self, id: EntityId, type_id: TypeId) -> EcsResult<u64> {
+        self.stamp(id, type_id).map(|stamp| self.tick - stamp)
+    }
+    /// Return `true` if the entity's component of type `C` was added or replaced with `set`
+    /// after tick `tick`, e.g. so a system that remembers the tick of its last run can skip
+    /// inputs that haven't changed. Modifications in place, e.g. with `borrow_mut`, are not seen.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist, or
+    /// `EcsError::ComponentNotFound` if it lacks a `C` or `enable_component_ticks` was never
+    /// called.
+    pub fn changed_since<C: Component>(&self, id: EntityId, tick: u64) -> EcsResult<bool> {
+        self.stamp(id, TypeId::of::<C>()).map(|stamp| stamp > tick)
+    }
+    /// Return a counter that increases on every structural change to the system.
+    ///
+    /// Structural changes are: creating or destroying an entity (including through `drain` and
+    /// `apply_diff`), setting a component (even one the entity already had), and removing a
+    /// component the entity actually had. Modifying components in place, e.g. with
+    /// `borrow_mut`, `update` or `iter_component_mut`, does not change the counter.
+    ///
+    /// This lets caches and editors cheaply detect whether anything changed since they last
+    /// looked.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+    /// Return an iterator over the entities whose creation moved the `generation` counter past
+    /// `generation`, i.e. those created since that value was read.
+    ///
+    /// Systems that initialize newly spawned entities can remember `generation()` after each
+    /// pass and only visit these next time.
+    pub fn created_since(&self, generation: u64) -> impl Iterator<Item = EntityId> + '_ {
+        self.created
+            .iter()
+            .filter(move |&(_, &created)| created > generation)
+            .map(|(id, _)| *id)
+    }
+    /// Return the latest generation allocated for the slot of `id`, or `None` if that slot was
+    /// never used.
+    ///
+    /// If `id` is stale, its own generation is behind the one returned here, which helps track
+    /// down use-after-destroy bugs.
+    pub fn generation_of(&self, id: EntityId) -> Option<u32> {
+        self.slots.get(&id.index()).cloned()
+    }
+    /// Return whether the provided entity is alive, dead, or was never allocated here. This is
+    /// more informative than `exists` when diagnosing why an operation failed on a stale ID.
+    pub fn status(&self, id: EntityId) -> EntityStatus {
+        if self.exists(id) {
+            return EntityStatus::Alive;
+        }
+        match self.generation_of(id) {
+            Some(current) if id.generation() <= current => {
+                EntityStatus::Dead { current_generation: current }
+            }
+            _ => EntityStatus::NeverExisted,
+        }
     }
     /// Return `true` if the provided entity exists in the system.
     pub fn exists(&self, id: EntityId) -> bool {
@@ -235,65 +1171,1055 @@ impl Ecs {
     }
     /// Destroy the provided entity, automatically removing any of its components.
     ///
-    /// Return `NotFound::Entity` if the entity does not exist or was already deleted.
+    /// The entity is detached from its parent, and its children become orphans. To destroy its
+    /// children as well, see `destroy_entity_hierarchy`.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist or was already deleted.
     pub fn destroy_entity(&mut self, id: EntityId) -> EcsResult<()> {
-        self.data.remove(&id).map(|_| ()).ok_or_else(|| NotFound::Entity(id))
+        self.destroy_entity_counted(id).map(|_| ())
+    }
+    /// Like `destroy_entity`, but return how many components the entity had, e.g. for
+    /// telemetry or to verify cleanup in tests.
+    pub fn destroy_entity_counted(&mut self, id: EntityId) -> EcsResult<usize> {
+        let mut map = self.take_entity(id)?;
+        self.cleanup_map(id, &mut map);
+        Ok(map.len())
+    }
+    /// Destroy each listed entity, returning the result of each `destroy_entity` in the same
+    /// order as `ids`, so callers can see which entities were already gone.
+    pub fn destroy_entities(&mut self, ids: &[EntityId]) -> Vec<EcsResult<()>> {
+        ids.iter().map(|id| self.destroy_entity(*id)).collect()
+    }
+    /// Destroy every entity that has all the components in `components`, returning how many
+    /// were destroyed, e.g. to clear all projectiles at once.
+    pub fn destroy_with(&mut self, components: &ComponentFilter) -> usize {
+        let doomed: Vec<_> = self.matching(components).collect();
+        doomed.into_iter().filter(|id| self.destroy_entity(*id).is_ok()).count()
+    }
+    /// Destroy every entity that has all the components in `components`, returning their IDs
+    /// and owned components, e.g. to remove all expired entities and recycle their data.
+    ///
+    /// Observers registered with `on_destroy` are called for each entity. Like with `drain`,
+    /// cleanup hooks registered with `register_cleanup` don't run, since the components are
+    /// handed back.
+    pub fn drain_with(&mut self, components: &ComponentFilter) -> Vec<(EntityId, ComponentMap)> {
+        let doomed: Vec<_> = self.matching(components).collect();
+        doomed.into_iter().filter_map(|id| self.take_entity(id).ok().map(|map| (id, map))).collect()
     }
     /// For the specified entity, add a component of type `C` to the system.
     ///
     /// If the entity already has a component `prev` of type `C`, return `Some(prev)`. If not,
-    /// return `None`. If the entity does not exist, return `NotFound::Entity`.
+    /// return `None`. If the entity does not exist, return `EcsError::EntityNotFound`.
     ///
     /// To modify an existing component in place, see `borrow_mut`.
     pub fn set<C: Component>(&mut self, id: EntityId, comp: C) -> EcsResult<Option<C>> {
-        self.data
-            .get_mut(&id)
-            .ok_or_else(|| NotFound::Entity(id))
-            .map(|map| map.set(comp))
+        self.name_type::<C>();
+        self.insert_component(id, TypeId::of::<C>(), Box::new(comp))
+            .map(|prev| prev.map(|old| downcast_box(old, "Ecs.set")))
+    }
+    /// Like `set`, but return both the previous component and a copy of the new one, e.g. for
+    /// an audit log that records exactly what changed without reading before writing.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn set_logged<C: Component + Clone>(&mut self,
+                                            id: EntityId,
+                                            comp: C)
+                                            -> EcsResult<ChangeRecord<C>> {
+        let new = comp.clone();
+        self.set(id, comp).map(|old| ChangeRecord { old, new })
     }
-    /// Return a clone of the requested entity's component of type `C`, or a `NotFound` variant
+    /// Replace the specified entity's component of type `C` with `new`, but only if it is
+    /// currently equal to `expected`, and return whether it was replaced. This guards updates
+    /// that assume a value hasn't changed, e.g. for lockstep validation.
+    ///
+    /// Return `EcsError::ComponentNotFound` if the entity has no `C`, or
+    /// `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn compare_and_set<C: Component + PartialEq>(&mut self,
+                                                     id: EntityId,
+                                                     expected: &C,
+                                                     new: C)
+                                                     -> EcsResult<bool> {
+        if self.borrow::<C>(id)? != expected {
+            return Ok(false);
+        }
+        self.set(id, new)?;
+        Ok(true)
+    }
+    /// Fold `incoming` into the specified entity's component of type `C` with `combine`, or
+    /// just add it if the entity has no `C` yet, e.g. to stack damage or sum forces.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn merge_with<C, F>(&mut self, id: EntityId, incoming: C, combine: F) -> EcsResult<()>
+        where C: Component,
+              F: Fn(&mut C, C)
+    {
+        match self.borrow_mut::<C>(id) {
+            Ok(existing) => combine(existing, incoming),
+            Err(EcsError::ComponentNotFound(_)) => {
+                self.set(id, incoming)?;
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+    /// Like `set`, but return a mutable reference to the stored component instead of the
+    /// previous one, to finish initializing it without a second lookup.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn set_mut<C: Component>(&mut self, id: EntityId, comp: C) -> EcsResult<&mut C> {
+        let type_id = TypeId::of::<C>();
+        self.name_type::<C>();
+        let (data, mut books) = self.split();
+        let map = data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        let (_, slot) = books.store(id, map, type_id, Box::new(comp));
+        Ok(slot.downcast_mut().expect("Ecs.set_mut: internal downcast error"))
+    }
+    /// Add a clone of `comp` to each listed entity, returning the result of each `set` in the
+    /// same order as `ids`.
+    pub fn set_many<C: Component + Clone>(&mut self,
+                                          ids: &[EntityId],
+                                          comp: C)
+                                          -> Vec<EcsResult<Option<C>>> {
+        ids.iter().map(|id| self.set(*id, comp.clone())).collect()
+    }
+    /// For the specified entity, add a pre-boxed component whose type is only known at runtime.
+    ///
+    /// This behaves like `set`, but the component type is given by `type_id`. It is meant for
+    /// deserializers and similar code that pick concrete types from a runtime registry.
+    ///
+    /// Return `EcsError::TypeMismatch` if `type_id` isn't the type of the boxed value, or
+    /// `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn set_boxed(&mut self,
+                     id: EntityId,
+                     type_id: TypeId,
+                     comp: Box<dyn Any>)
+                     -> EcsResult<Option<Box<dyn Any>>> {
+        let found = (*comp).type_id();
+        if found != type_id {
+            return Err(EcsError::TypeMismatch {
+                expected: type_id,
+                found,
+            });
+        }
+        self.insert_component(id, type_id, comp)
+    }
+    /// Replace all of the specified entity's components with the ones in `map`, returning the
+    /// entity's previous components.
+    ///
+    /// The swap is atomic: no method can observe the entity with a mix of old and new
+    /// components. Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn set_components(&mut self, id: EntityId, map: ComponentMap) -> EcsResult<ComponentMap> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        Ok(self.replace_map(id, map))
+    }
+    /// Exchange all components between the entities `a` and `b`, which keep their IDs, e.g. for
+    /// "body swap" mechanics. Return `EcsError::EntityNotFound` if either entity does not exist.
+    pub fn swap_entities(&mut self, a: EntityId, b: EntityId) -> EcsResult<()> {
+        for id in &[a, b] {
+            if !self.exists(*id) {
+                return Err(EcsError::EntityNotFound(*id));
+            }
+        }
+        if a != b {
+            let map_a = self.replace_map(a, ComponentMap::new());
+            let map_b = self.replace_map(b, map_a);
+            self.replace_map(a, map_b);
+        }
+        Ok(())
+    }
+    /// Give entity `to` a copy of entity `from`'s component of type `C`, replacing any `C` it
+    /// already had, e.g. to share a buff. `from` keeps its component.
+    ///
+    /// Return `EcsError::EntityNotFound` if either entity does not exist, or
+    /// `EcsError::ComponentNotFound` if `from` has no `C`.
+    pub fn copy_component<C: Component + Clone>(&mut self,
+                                                from: EntityId,
+                                                to: EntityId)
+                                                -> EcsResult<()> {
+        let comp = self.get::<C>(from)?;
+        self.set(to, comp).map(|_| ())
+    }
+    /// Remove all of the specified entity's components, keeping the entity and its ID, e.g. to
+    /// recycle it for a new purpose. Return `EcsError::EntityNotFound` if the entity does not
+    /// exist.
+    pub fn clear_components(&mut self, id: EntityId) -> EcsResult<()> {
+        let mut old = self.set_components(id, ComponentMap::new())?;
+        self.cleanup_map(id, &mut old);
+        Ok(())
+    }
+    /// Reserve room for at least `additional` more components on the specified entity, e.g.
+    /// before adding dozens of components to a complex actor one by one.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn reserve_components(&mut self, id: EntityId, additional: usize) -> EcsResult<()> {
+        let map = self.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        map.map.reserve(additional);
+        Ok(())
+    }
+    /// Remove the specified entity's component of type `C` from the system.
+    ///
+    /// If the entity had a component `prev` of type `C`, return `Some(prev)`. If not, return
+    /// `None`. If the entity does not exist, return `EcsError::EntityNotFound`.
+    pub fn remove<C: Component>(&mut self, id: EntityId) -> EcsResult<Option<C>> {
+        self.remove_component(id, &TypeId::of::<C>())
+            .map(|prev| prev.map(|old| downcast_box(old, "Ecs.remove")))
+    }
+    /// Remove the specified entity's component of type `C` and return it by value.
+    ///
+    /// Unlike `remove`, a missing component is an error: return `EcsError::ComponentNotFound` if
+    /// the entity has no `C`, or `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn take<C: Component>(&mut self, id: EntityId) -> EcsResult<C> {
+        self.remove(id)?.ok_or_else(|| EcsError::ComponentNotFound(TypeId::of::<C>()))
+    }
+    /// Remove the specified entity's component whose type is only known at runtime.
+    ///
+    /// This behaves like `remove`, but the component type is given by `type_id` and the removed
+    /// component, if any, is returned boxed.
+    pub fn remove_by_type(&mut self,
+                          id: EntityId,
+                          type_id: TypeId)
+                          -> EcsResult<Option<Box<dyn Any>>> {
+        self.remove_component(id, &type_id)
+    }
+    /// Apply `f` to every component of type `C` in the system, e.g. to halve every movement
+    /// speed while live-tuning, and return the number of components touched.
+    pub fn map_all<C: Component, F: FnMut(&mut C)>(&mut self, mut f: F) -> usize {
+        let mut touched = 0;
+        for id in self.index.get(&TypeId::of::<C>()).into_iter().flat_map(|ids| ids.iter()) {
+            let map = self.data.get_mut(id).expect("Ecs.map_all: internal index error");
+            f(map.borrow_mut().expect("Ecs.map_all: internal index error"));
+            touched += 1;
+        }
+        touched
+    }
+    /// For every entity matching `filter` that has a component of type `A`, call `f` with a
+    /// mutable reference to it and the entity's other components, e.g. to add each entity's
+    /// velocity to its position. Return the number of entities visited.
+    ///
+    /// While `f` runs, the `A` is taken out of the map it receives, so the map doesn't contain
+    /// an `A` itself.
+    pub fn map_filtered<A, F>(&mut self, filter: &ComponentFilter, mut f: F) -> usize
+        where A: Component,
+              F: FnMut(&mut A, &ComponentMap)
+    {
+        let type_id = TypeId::of::<A>();
+        let ids: Vec<_> = self.matching(filter).collect();
+        let mut visited = 0;
+        for id in ids {
+            let map = self.data.get_mut(&id).expect("Ecs.map_filtered: internal entity error");
+            if let Some(mut comp) = map.map.remove(&type_id) {
+                f(comp.downcast_mut().expect("Ecs.map_filtered: internal downcast error"), map);
+                map.map.insert(type_id, comp);
+                visited += 1;
+            }
+        }
+        visited
+    }
+    /// Remove the component of type `C` from every entity where `f` returns `true` for it, e.g.
+    /// to strip expired timers. Return the number of components removed.
+    pub fn remove_if<C: Component, F: Fn(&C) -> bool>(&mut self, f: F) -> usize {
+        let doomed: Vec<_> = self.iter_component::<C>()
+            .filter(|&(_, comp)| f(comp))
+            .map(|(id, _)| id)
+            .collect();
+        for id in &doomed {
+            let _ = self.remove_component(*id, &TypeId::of::<C>());
+        }
+        doomed.len()
+    }
+    /// Replace the specified entity's component of type `C` with `f(old)`.
+    ///
+    /// Unlike `borrow_mut`, `f` takes and returns the component by value, which suits component
+    /// types that transform themselves through consuming methods. Return `EcsError::EntityNotFound`
+    /// if the entity does not exist, or `EcsError::ComponentNotFound` if it has no `C`.
+    pub fn update<C: Component, F: FnOnce(C) -> C>(&mut self, id: EntityId, f: F) -> EcsResult<()> {
+        let map = self.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        let old = map.remove::<C>().ok_or_else(|| EcsError::ComponentNotFound(TypeId::of::<C>()))?;
+        map.set(f(old));
+        Ok(())
+    }
+    /// Replace the specified entity's component of type `C` with `f` applied to a clone of it,
+    /// and return the new value, e.g. to log it.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist, or
+    /// `EcsError::ComponentNotFound` if it has no `C`.
+    pub fn compute<C, F>(&mut self, id: EntityId, f: F) -> EcsResult<C>
+        where C: Component + Clone,
+              F: FnOnce(C) -> C
+    {
+        let comp = self.borrow_mut::<C>(id)?;
+        *comp = f(comp.clone());
+        Ok(comp.clone())
+    }
+    /// Exchange the values of the specified entity's `A` and `B` components, converting each
+    /// into the other's type. This suits distinct component types that share a representation,
+    /// e.g. `PrimaryWeapon` and `SecondaryWeapon` wrapping the same `Weapon`.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist, or
+    /// `EcsError::ComponentNotFound` without changing anything if it lacks either component.
+    pub fn swap_components<A, B>(&mut self, id: EntityId) -> EcsResult<()>
+        where A: Component + From<B>,
+              B: Component + From<A>
+    {
+        let map = self.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        for type_id in &[TypeId::of::<A>(), TypeId::of::<B>()] {
+            if !map.contains_type_id(type_id) {
+                return Err(EcsError::ComponentNotFound(*type_id));
+            }
+        }
+        if TypeId::of::<A>() == TypeId::of::<B>() {
+            return Ok(());
+        }
+        let a = map.remove::<A>().expect("Ecs.swap_components: internal component error");
+        let b = map.remove::<B>().expect("Ecs.swap_components: internal component error");
+        map.set(A::from(b));
+        map.set(B::from(a));
+        Ok(())
+    }
+    /// Return a clone of the requested entity's component of type `C`, or an `EcsError` variant
     /// if the entity does not exist or does not have that component.
     ///
     /// To examine or modify a component without making a clone, see `borrow` and `borrow_mut`.
     pub fn get<C: Component + Clone>(&self, id: EntityId) -> EcsResult<C> {
         self.data
             .get(&id)
-            .ok_or_else(|| NotFound::Entity(id))
+            .ok_or(EcsError::EntityNotFound(id))
             .and_then(|map| map.get())
     }
+    /// Return a clone of the requested entity's component of type `C`, or `C::default()` if the
+    /// entity doesn't have one. Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn get_or_default<C: Component + Clone + Default>(&self, id: EntityId) -> EcsResult<C> {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok(map.get().unwrap_or_default())
+    }
+    /// Return clones of the requested entity's components of types `A` and `B`, or the first
+    /// `EcsError` variant encountered.
+    pub fn get2<A, B>(&self, id: EntityId) -> EcsResult<(A, B)>
+        where A: Component + Clone,
+              B: Component + Clone
+    {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok((map.get()?, map.get()?))
+    }
+    /// Like `get2`, but for three component types.
+    pub fn get3<A, B, C>(&self, id: EntityId) -> EcsResult<(A, B, C)>
+        where A: Component + Clone,
+              B: Component + Clone,
+              C: Component + Clone
+    {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok((map.get()?, map.get()?, map.get()?))
+    }
     /// Return `true` if the specified entity has a component of type `C` in the system, or
-    /// `NotFound::Entity` if the entity does not exist.
+    /// `EcsError::EntityNotFound` if the entity does not exist.
     pub fn has<C: Component>(&self, id: EntityId) -> EcsResult<bool> {
         self.data
             .get(&id)
-            .ok_or_else(|| NotFound::Entity(id))
+            .ok_or(EcsError::EntityNotFound(id))
             .map(|map| map.contains::<C>())
     }
-    /// Return `true` if each component type in the filter is present on the entity `id`.
+    /// Return `true` if the specified entity has no components at all, e.g. right after
+    /// `create_entity` or once it has lost everything and may be worth cleaning up.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn is_bare(&self, id: EntityId) -> EcsResult<bool> {
+        self.data.get(&id).ok_or(EcsError::EntityNotFound(id)).map(ComponentMap::is_empty)
+    }
+    /// Return the type names of all the requested entity's components, sorted alphabetically, or
+    /// `EcsError::EntityNotFound` if the entity does not exist.
+    ///
+    /// Names are recorded the first time a type is added with `set`, directly or through a
+    /// `ComponentMap`, or registered with `register_clone`. Types that have only ever been added
+    /// through type-erased paths such as `set_boxed` otherwise are listed as `"<unknown>"`.
+    pub fn component_type_names(&self, id: EntityId) -> EcsResult<Vec<&'static str>> {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        let mut names: Vec<_> = map.type_ids()
+            .map(|type_id| self.type_names.get(&type_id).cloned().unwrap_or("<unknown>"))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+    /// Return a hash of the requested entity's set of component types, so entities can be
+    /// grouped or compared by archetype with a single integer. Entities with the same component
+    /// types always have the same signature; different sets collide only with the usual hashing
+    /// odds.
+    ///
+    /// The signature is cached on the entity until its set of component types changes, so
+    /// repeated calls are cheap. Like `TypeId`s, signatures are only comparable within one run
+    /// of the program. Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn signature(&self, id: EntityId) -> EcsResult<u64> {
+        self.data.get(&id).map(ComponentMap::signature).ok_or(EcsError::EntityNotFound(id))
+    }
+    /// Return `true` if each component type in the filter is present on the entity `id`, and
+    /// none of the filter's excluded types are.
+    ///
+    /// If every type in the filter was registered with `register_presence_bit`, this is a single
+    /// bitmask comparison.
     pub fn has_all(&self, id: EntityId, set: &ComponentFilter) -> EcsResult<bool> {
-        let map = try!(self.data.get(&id).ok_or_else(|| NotFound::Entity(id)));
-        Ok(set.iter().all(|type_id| map.contains_type_id(&type_id)))
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok(Self::map_matches(map, set, self.filter_masks(set)))
+    }
+    /// Return `pred` applied to the entity `id`'s component map, for value-dependent checks
+    /// that a `ComponentFilter` can't express, or `EcsError::EntityNotFound` if the entity
+    /// does not exist.
+    pub fn matches<F: Fn(&ComponentMap) -> bool>(&self, id: EntityId, pred: F) -> EcsResult<bool> {
+        self.data.get(&id).map(pred).ok_or(EcsError::EntityNotFound(id))
+    }
+    /// Return the result of `has_all` for each listed entity, in the same order as `ids`, e.g.
+    /// to test candidates found by a spatial query.
+    pub fn has_all_many(&self, ids: &[EntityId], filter: &ComponentFilter) -> Vec<EcsResult<bool>> {
+        let masks = self.filter_masks(filter);
+        ids.iter()
+            .map(|id| {
+                let map = self.data.get(id).ok_or(EcsError::EntityNotFound(*id))?;
+                Ok(Self::map_matches(map, filter, masks))
+            })
+            .collect()
+    }
+    /// Like `has_all`, but take the required component types as a slice of `TypeId`s, for
+    /// queries built at runtime.
+    pub fn has_all_types(&self, id: EntityId, types: &[TypeId]) -> EcsResult<bool> {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        let mask = self.presence_mask(types.iter().cloned());
+        Ok(Self::map_has_types(map, types.iter().cloned(), mask))
+    }
+    /// Return `true` if the entity `id` has exactly the component types required by the filter
+    /// and no others. The filter's excluded types are ignored.
+    pub fn has_exactly(&self, id: EntityId, set: &ComponentFilter) -> EcsResult<bool> {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok(map.len() == set.set.len() && set.iter().all(|type_id| map.contains_type_id(&type_id)))
+    }
+    /// Return `true` if at least one component type in the filter is present on the entity
+    /// `id`. The filter's excluded types are ignored.
+    ///
+    /// Like `has_all`, this is a single bitmask comparison if every type in the filter was
+    /// registered with `register_presence_bit`.
+    pub fn has_any(&self, id: EntityId, set: &ComponentFilter) -> EcsResult<bool> {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok(match self.presence_mask(set.iter()) {
+            Some(mask) => map.mask & mask != 0,
+            None => set.iter().any(|type_id| map.contains_type_id(&type_id)),
+        })
+    }
+    /// Return the filter's required types that the entity `id` actually has, as a new filter,
+    /// e.g. for an inspector showing which of a list of components are present. The filter's
+    /// excluded types are ignored.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn which_of(&self, id: EntityId, set: &ComponentFilter) -> EcsResult<ComponentFilter> {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        let mut present = ComponentFilter::new();
+        for type_id in set.iter().filter(|type_id| map.contains_type_id(type_id)) {
+            present.add_type_id(type_id);
+            if let Some(name) = set.names.get(&type_id) {
+                present.names.insert(type_id, name);
+            }
+        }
+        Ok(present)
+    }
+    /// Return the component types that both entities have, as a filter, e.g. to debug why two
+    /// entities are or aren't matched by the same query.
+    ///
+    /// Return `EcsError::EntityNotFound` if either entity does not exist.
+    pub fn shared_types(&self, a: EntityId, b: EntityId) -> EcsResult<ComponentFilter> {
+        let map_a = self.data.get(&a).ok_or(EcsError::EntityNotFound(a))?;
+        let map_b = self.data.get(&b).ok_or(EcsError::EntityNotFound(b))?;
+        let mut shared = ComponentFilter::new();
+        for type_id in map_a.type_ids().filter(|type_id| map_b.contains_type_id(type_id)) {
+            shared.add_type_id(type_id);
+            if let Some(name) = self.type_names.get(&type_id) {
+                shared.names.insert(type_id, name);
+            }
+        }
+        Ok(shared)
+    }
+    /// Assign component type `C` a bit in the per-entity presence mask, and return its index.
+    ///
+    /// Queries whose filters only contain registered types (`has_all`, `has_any`,
+    /// `collect_with`, ...) test each entity with a bitwise AND instead of hash lookups. Up to
+    /// 128 types can be registered; after that, this returns `None` and queries involving `C`
+    /// keep using the hash-based path. Registering the same type again returns its existing
+    /// bit.
+    pub fn register_presence_bit<C: Component>(&mut self) -> Option<u32> {
+        let type_id = TypeId::of::<C>();
+        if let Some(bit) = self.bits.get(&type_id) {
+            return Some(*bit);
+        }
+        if self.bits.len() >= MAX_PRESENCE_BITS {
+            return None;
+        }
+        let bit = self.bits.len() as u32;
+        self.bits.insert(type_id, bit);
+        for id in self.index.get(&type_id).into_iter().flat_map(|ids| ids.iter()) {
+            self.data
+                .get_mut(id)
+                .expect("Ecs.register_presence_bit: internal index error")
+                .mask |= 1 << bit;
+        }
+        Some(bit)
     }
     /// Return a shared reference to the requested entity's component of type `C`, or a
-    /// `NotFound` variant if the entity does not exist or does not have that component.
+    /// `EcsError` variant if the entity does not exist or does not have that component.
     pub fn borrow<C: Component>(&self, id: EntityId) -> EcsResult<&C> {
         self.data
             .get(&id)
-            .ok_or_else(|| NotFound::Entity(id))
+            .ok_or(EcsError::EntityNotFound(id))
             .and_then(|map| map.borrow())
     }
+    /// Like `borrow`, but return `None` if either the entity or the component is missing.
+    pub fn try_borrow<C: Component>(&self, id: EntityId) -> Option<&C> {
+        self.borrow(id).ok()
+    }
+    /// Return a shared reference to the requested entity's component of type `C`, first adding
+    /// `C::default()` if the entity doesn't have one, covering the "ensure present, then read"
+    /// pattern in one call.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn borrow_or_insert<C: Component + Default>(&mut self, id: EntityId) -> EcsResult<&C> {
+        self.entry(id).map(|entry| &*entry.or_insert_with(C::default))
+    }
+    /// Return a mutable reference to the requested entity's component of type `C`, first adding
+    /// `default` if the entity doesn't have one. `default` is dropped if the component exists.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn borrow_mut_or<C: Component>(&mut self, id: EntityId, default: C) -> EcsResult<&mut C> {
+        self.entry(id).map(|entry| entry.or_insert(default))
+    }
+    /// Run `f` on a shared reference to the requested entity's component of type `C`, e.g. to
+    /// log it without cloning. Return the usual `EcsError` variant if the entity or component is
+    /// missing, in which case `f` isn't called.
+    pub fn inspect<C: Component, F: FnOnce(&C)>(&self, id: EntityId, f: F) -> EcsResult<()> {
+        self.borrow(id).map(f)
+    }
+    /// Return `f` applied to a shared reference to the requested entity's component of type
+    /// `C`, or an `EcsError` variant if the entity does not exist or does not have that
+    /// component. Unlike `get`, this works for components that aren't `Clone`.
+    pub fn get_with<C, R, F>(&self, id: EntityId, f: F) -> EcsResult<R>
+        where C: Component,
+              F: FnOnce(&C) -> R
+    {
+        self.borrow(id).map(f)
+    }
+    /// Return a shared reference to the requested entity's component whose type is only known
+    /// at runtime, as `&dyn Any` for the caller to downcast, or an `EcsError` variant if the
+    /// entity does not exist or does not have that component.
+    pub fn get_any(&self, id: EntityId, type_id: TypeId) -> EcsResult<&dyn Any> {
+        let map = self.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        map.map.get(&type_id).map(|comp| &**comp).ok_or(EcsError::ComponentNotFound(type_id))
+    }
+    /// Return `f` applied to all of the requested entity's components, so code reading several
+    /// of them looks the entity up only once.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist, in which case `f` isn't
+    /// called.
+    pub fn with_entity<R, F>(&self, id: EntityId, f: F) -> EcsResult<R>
+        where F: FnOnce(&ComponentMap) -> R
+    {
+        self.data.get(&id).map(f).ok_or(EcsError::EntityNotFound(id))
+    }
+    /// Like `with_entity`, but `f` can also modify, add and remove the entity's components.
+    ///
+    /// The system's indices are brought up to date afterwards if the set of component types
+    /// changed. Replacing a component with one of the same type through the map is not counted
+    /// by `generation` or the component ticks, unlike with `set`.
+    ///
+    /// Components removed through the map are handed to `f`, so their `on_remove` hooks
+    /// registered with `register_cleanup` are not run. While checkpoints are in use, the
+    /// entity's components of types registered with `register_clone` are copied first so
+    /// removals can be rolled back, and removing a component of any other type panics.
+    pub fn with_entity_mut<R, F>(&mut self, id: EntityId, f: F) -> EcsResult<R>
+        where F: FnOnce(&mut ComponentMap) -> R
+    {
+        let bits = &self.bits;
+        let cloners = &self.cloners;
+        let journaling = self.journal.is_some();
+        let map = self.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        let before: HashSet<_> = map.type_ids().collect();
+        let snapshot: HashMap<_, _> = if journaling {
+            map.map
+                .iter()
+                .filter_map(|(type_id, comp)| {
+                    cloners.get(type_id).map(|clone| (*type_id, clone(&**comp)))
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let result = f(map);
+        self.type_names.extend(map.names.drain());
+        let after: HashSet<_> = map.type_ids().collect();
+        if before != after {
+            map.mask = after.iter()
+                .filter_map(|type_id| bits.get(type_id))
+                .fold(0, |mask, bit| mask | 1 << bit);
+            for type_id in after.difference(&before) {
+                self.index_component(id, *type_id);
+                self.record(EcsEvent::ComponentSet(id, *type_id), None);
+            }
+            for type_id in before.difference(&after) {
+                self.unindex(id, type_id);
+                self.log_event(EcsEvent::ComponentRemoved(id, *type_id));
+                if journaling {
+                    let prev = snapshot.get(type_id).unwrap_or_else(|| {
+                        panic!("Ecs.with_entity_mut: component type {:?} was removed after a \
+                                checkpoint but was not registered with Ecs.register_clone",
+                               type_id)
+                    });
+                    self.record(EcsEvent::ComponentRemoved(id, *type_id), Some(&**prev));
+                }
+            }
+            self.generation += 1;
+        }
+        Ok(result)
+    }
+    /// Return the requested entity's component of type `C` as a copy-on-write value, or an
+    /// `EcsError` variant if the entity does not exist or does not have that component.
+    ///
+    /// The result starts out borrowing the stored component, and is only cloned the first time
+    /// it is mutated through `Cow::to_mut` (or taken with `Cow::into_owned`), so read-only uses
+    /// never pay for a clone. Changes to the copy don't affect the stored component.
+    pub fn get_cow<C: Component + Clone>(&self, id: EntityId) -> EcsResult<Cow<'_, C>> {
+        self.borrow(id).map(Cow::Borrowed)
+    }
+    /// Like `get`, but return `None` if either the entity or the component is missing.
+    pub fn try_get<C: Component + Clone>(&self, id: EntityId) -> Option<C> {
+        self.get(id).ok()
+    }
     /// Return a mutable reference to the requested entity's component of type `C`, or a
-    /// `NotFound` variant if the entity does not exist or does not have that component.
+    /// `EcsError` variant if the entity does not exist or does not have that component.
     pub fn borrow_mut<C: Component>(&mut self, id: EntityId) -> EcsResult<&mut C> {
         self.data
             .get_mut(&id)
-            .ok_or_else(|| NotFound::Entity(id))
+            .ok_or(EcsError::EntityNotFound(id))
             .and_then(|map| map.borrow_mut())
     }
+    borrow_mut_tuple! {
+        /// Return mutable references to two different component types on the same entity.
+        ///
+        /// Return `EcsError::Aliased` if `A` and `B` are the same type, or the usual `EcsError`
+        /// variant if the entity or either component is missing.
+        fn borrow_mut_two<A, B>
+    }
+    borrow_mut_tuple! {
+        /// Like `borrow_mut_two`, but for three different component types.
+        fn borrow_mut_three<A, B, C>
+    }
+    borrow_mut_tuple! {
+        /// Like `borrow_mut_two`, but for four different component types.
+        fn borrow_mut_four<A, B, C, D>
+    }
+    /// Return mutable references to the components of type `C` on two different entities,
+    /// e.g. for pairwise collision response.
+    ///
+    /// Return `EcsError::AliasedEntity` if `a` and `b` are the same entity, or the usual
+    /// `EcsError` variant if either entity or component is missing.
+    pub fn borrow_mut_pair<C: Component>(&mut self,
+                                         a: EntityId,
+                                         b: EntityId)
+                                         -> EcsResult<(&mut C, &mut C)> {
+        if a == b {
+            return Err(EcsError::AliasedEntity(a));
+        }
+        match self.data.get_disjoint_mut([&a, &b]) {
+            [Some(map_a), Some(map_b)] => Ok((map_a.borrow_mut()?, map_b.borrow_mut()?)),
+            [None, _] => Err(EcsError::EntityNotFound(a)),
+            [_, None] => Err(EcsError::EntityNotFound(b)),
+        }
+    }
+    /// Like `borrow_mut_pair`, but for any number of entities: return mutable references to the
+    /// components of type `C` on each of `ids`, in the same order, e.g. for a contact-island
+    /// solver.
+    ///
+    /// Return `EcsError::AliasedEntity` if an ID is listed more than once, or the usual
+    /// `EcsError` variant if an entity or component is missing. This walks every entity in the
+    /// system once, so prefer `borrow_mut_pair` for two entities.
+    pub fn borrow_mut_many<C: Component>(&mut self, ids: &[EntityId]) -> EcsResult<Vec<&mut C>> {
+        let mut wanted = HashSet::new();
+        for id in ids {
+            if !self.exists(*id) {
+                return Err(EcsError::EntityNotFound(*id));
+            }
+            if !wanted.insert(*id) {
+                return Err(EcsError::AliasedEntity(*id));
+            }
+        }
+        // each entity's map is borrowed at most once, since the store's entries are disjoint
+        let mut maps: HashMap<_, _> = self.data
+            .iter_mut()
+            .filter(|&(id, _)| wanted.contains(id))
+            .collect();
+        ids.iter()
+            .map(|id| {
+                let map = maps.remove(id).expect("Ecs.borrow_mut_many: internal entity error");
+                map.borrow_mut()
+            })
+            .collect()
+    }
+    /// Return a mutable reference to the component of type `A` on entity `a` along with one to
+    /// the component of type `B` on entity `b`, e.g. an attacker's stamina and a defender's
+    /// health.
+    ///
+    /// `a` and `b` may be the same entity as long as `A` and `B` are different types, like with
+    /// `borrow_mut_two`; otherwise return `EcsError::Aliased`. Return the usual `EcsError`
+    /// variant if either entity or component is missing.
+    pub fn borrow_mut_across<A: Component, B: Component>(&mut self,
+                                                         a: EntityId,
+                                                         b: EntityId)
+                                                         -> EcsResult<(&mut A, &mut B)> {
+        if a == b {
+            return self.borrow_mut_two(a);
+        }
+        match self.data.get_disjoint_mut([&a, &b]) {
+            [Some(map_a), Some(map_b)] => Ok((map_a.borrow_mut()?, map_b.borrow_mut()?)),
+            [None, _] => Err(EcsError::EntityNotFound(a)),
+            [_, None] => Err(EcsError::EntityNotFound(b)),
+        }
+    }
     /// Return an iterator over every ID in the system.
-    #[allow(needless_lifetimes)] // https://github.com/Manishearth/rust-clippy/issues/740
-    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = EntityId> + 'a> {
-        Box::new(self.data.keys().cloned())
+    pub fn iter(&self) -> Box<dyn Iterator<Item = EntityId> + '_> {
+        Box::new(self.entries().map(|(id, _)| id))
+    }
+    /// Return every live ID in creation order as a borrowed slice, without allocating, e.g. to
+    /// split the entities into chunks for deterministic parallel work.
+    ///
+    /// Only systems created with `new_ordered` keep their IDs in a list; return `None` for any
+    /// other, and use `collect` instead.
+    pub fn entity_ids(&self) -> Option<&[EntityId]> {
+        self.order.as_deref()
+    }
+    /// Partition every live ID into `n` disjoint chunks whose sizes differ by at most one, e.g.
+    /// to hand each chunk to a thread for read-only work. Some chunks are empty if there are
+    /// fewer than `n` entities.
+    ///
+    /// The IDs are sorted first, so the same set of entities is always split the same way.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn chunks(&self, n: usize) -> Vec<Vec<EntityId>> {
+        assert!(n > 0, "Ecs.chunks: chunk count must be non-zero");
+        let mut ids: Vec<_> = self.iter().collect();
+        ids.sort();
+        let (size, extra) = (ids.len() / n, ids.len() % n);
+        let mut rest = &ids[..];
+        (0..n)
+            .map(|i| {
+                let (chunk, tail) = rest.split_at(size + usize::from(i < extra));
+                rest = tail;
+                chunk.to_vec()
+            })
+            .collect()
+    }
+    /// Return an iterator over every entity's ID and full set of components, for generic tools
+    /// like serializers and debuggers that don't know the component types at compile time.
+    pub fn iter_entities(&self) -> impl Iterator<Item = (EntityId, &ComponentMap)> + '_ {
+        self.entries()
+    }
+    /// Like `iter_entities`, but only for the entities matching `filter`, so reflection-driven
+    /// systems can read whichever components they need without a second lookup per entity.
+    pub fn iter_with_maps<'a>(&'a self,
+                              filter: &'a ComponentFilter)
+                              -> impl Iterator<Item = (EntityId, &'a ComponentMap)> + 'a {
+        self.matching(filter).map(move |id| (id, &self.data[&id]))
+    }
+    /// Return the number of entities that have a component of type `C`.
+    ///
+    /// This uses an internal per-type index, so it takes constant time regardless of how many
+    /// entities are in the system.
+    pub fn count<C: Component>(&self) -> usize {
+        self.index.get(&TypeId::of::<C>()).map_or(0, HashSet::len)
+    }
+    /// Return how many entities have each component type present in the system, e.g. for a
+    /// debug HUD. Pair with `component_type_names` for readable labels.
+    ///
+    /// Like `count`, this reads the per-type index, so it takes time proportional to the number
+    /// of component types rather than entities.
+    pub fn component_type_counts(&self) -> HashMap<TypeId, usize> {
+        self.index.iter().map(|(type_id, ids)| (*type_id, ids.len())).collect()
+    }
+    /// Return the total number of components in the system, summed over all entities, e.g. as a
+    /// telemetry metric that catches component leaks.
+    ///
+    /// This reads the per-type index, so it takes time proportional to the number of component
+    /// types rather than entities.
+    pub fn total_components(&self) -> usize {
+        self.index.values().map(HashSet::len).sum()
+    }
+    /// Return each distinct set of component types found in the system, sorted, paired with the
+    /// number of entities that have exactly that set, most common first. This shows which
+    /// combinations dominate, e.g. to decide what to optimize.
+    ///
+    /// This visits every entity and component, so it is meant for diagnostics.
+    pub fn archetype_census(&self) -> Vec<(Vec<TypeId>, usize)> {
+        let mut census: HashMap<Vec<TypeId>, usize> = HashMap::new();
+        for map in self.data.values() {
+            let mut types: Vec<_> = map.type_ids().collect();
+            types.sort();
+            *census.entry(types).or_insert(0) += 1;
+        }
+        let mut census: Vec<_> = census.into_iter().collect();
+        census.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        census
+    }
+    /// Return `true` if at least one entity in the system has a component of type `C`.
+    ///
+    /// This takes constant time thanks to the per-type index.
+    pub fn any<C: Component>(&self) -> bool {
+        self.index.contains_key(&TypeId::of::<C>())
+    }
+    /// Return an iterator over the IDs of every entity that has a component of type `C`.
+    ///
+    /// This is the simplest single-type query: it doesn't need a `ComponentFilter`, doesn't
+    /// allocate, and only visits matching entities thanks to the per-type index.
+    pub fn with<C: Component>(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.index.get(&TypeId::of::<C>()).into_iter().flat_map(|ids| ids.iter().cloned())
+    }
+    /// Return an iterator over every entity that has a component of type `C`, paired with a
+    /// shared reference to that component.
+    ///
+    /// Only entities that actually have a `C` are visited, so this is proportional to
+    /// `count::<C>()` rather than to the total number of entities.
+    pub fn iter_component<C: Component>(&self) -> impl Iterator<Item = (EntityId, &C)> + '_ {
+        self.with::<C>()
+            .map(move |id| {
+                let comp = self.borrow::<C>(id)
+                    .expect("Ecs.iter_component: internal index error");
+                (id, comp)
+            })
+    }
+    /// Return an iterator over every entity that has a component of type `C` for which `pred`
+    /// returns `true`, e.g. all entities whose health dropped to zero. Like `iter_component`,
+    /// only the entities having a `C` are visited.
+    pub fn iter_component_where<'a, C, F>(&'a self, pred: F) -> impl Iterator<Item = EntityId> + 'a
+        where C: Component,
+              F: Fn(&C) -> bool + 'a
+    {
+        self.iter_component::<C>().filter(move |&(_, comp)| pred(comp)).map(|(id, _)| id)
+    }
+    /// Return an iterator over every entity that has components of types `A` and `B`, paired
+    /// with shared references to them.
+    ///
+    /// Like `collect_with`, only the entities having the rarer of the two types are visited.
+    pub fn iter_two<A, B>(&self) -> impl Iterator<Item = (EntityId, &A, &B)> + '_
+        where A: Component,
+              B: Component
+    {
+        let types = [TypeId::of::<A>(), TypeId::of::<B>()];
+        self.candidates(types.iter().cloned()).1.filter_map(move |id| {
+            let map = &self.data[&id];
+            Some((id, map.borrow().ok()?, map.borrow().ok()?))
+        })
+    }
+    /// Like `iter_two`, but for three component types, e.g. for a transform update.
+    pub fn iter_three<A, B, C>(&self) -> impl Iterator<Item = (EntityId, &A, &B, &C)> + '_
+        where A: Component,
+              B: Component,
+              C: Component
+    {
+        let types = [TypeId::of::<A>(), TypeId::of::<B>(), TypeId::of::<C>()];
+        self.candidates(types.iter().cloned()).1.filter_map(move |id| {
+            let map = &self.data[&id];
+            Some((id, map.borrow().ok()?, map.borrow().ok()?, map.borrow().ok()?))
+        })
+    }
+    /// Like `iter_component`, but yield `EcsError::Corruption` for a component that isn't
+    /// actually a `C` instead of panicking, for servers that must not crash on a single corrupt
+    /// entity.
+    pub fn iter_component_checked<C>(&self) -> impl Iterator<Item = EcsResult<(EntityId, &C)>> + '_
+        where C: Component
+    {
+        self.with::<C>().map(move |id| {
+            let map = self.data.get(&id).ok_or(EcsError::Corruption(TypeId::of::<C>()))?;
+            map.try_borrow().map(|comp| (id, comp))
+        })
+    }
+    /// Return an iterator over every entity that has a component of type `C`, paired with a
+    /// mutable reference to that component.
+    ///
+    /// The iterator borrows the whole `Ecs` mutably, so entities and components can't be added
+    /// or removed while iterating. Unlike `iter_component`, this visits every entity.
+    pub fn iter_component_mut<C: Component>(&mut self)
+                                            -> impl Iterator<Item = (EntityId, &mut C)> + '_ {
+        self.data
+            .iter_mut()
+            .filter_map(|(id, map)| map.borrow_mut::<C>().ok().map(|comp| (*id, comp)))
+    }
+    /// Return a rough estimate of the bytes used by the system's entities and components, e.g.
+    /// for a profiling dashboard or to decide when to `compact`.
+    ///
+    /// This counts each entity's map entry, each component's inline size and map entry, and the
+    /// per-type index, all at their current capacity. Heap memory owned by the components
+    /// themselves (a `Vec`'s buffer, say) and hashing overhead are not counted.
+    pub fn memory_estimate(&self) -> usize {
+        let entity = mem::size_of::<EntityId>() + mem::size_of::<ComponentMap>();
+        let slot = mem::size_of::<TypeId>() + mem::size_of::<Box<dyn Any>>();
+        let comps: usize = self.data
+            .values()
+            .flat_map(|map| map.map.values())
+            .map(|comp| mem::size_of_val(&**comp))
+            .sum();
+        let slots: usize = self.data.values().map(|map| map.map.capacity() * slot).sum();
+        let index: usize = self.index
+            .values()
+            .map(|ids| ids.capacity() * mem::size_of::<EntityId>())
+            .sum();
+        self.data.capacity() * entity + slots + comps + index
+    }
+    /// Release memory left over from entities and components that no longer exist, e.g. during
+    /// a loading screen after heavy churn.
+    ///
+    /// This shrinks the entity store, every entity's component map and all secondary indices,
+    /// so it takes time linear in the number of entities and components.
+    pub fn compact(&mut self) {
+        self.data.shrink_to_fit();
+        for map in self.data.values_mut() {
+            map.map.shrink_to_fit();
+        }
+        self.index.shrink_to_fit();
+        for ids in self.index.values_mut() {
+            ids.shrink_to_fit();
+        }
+        self.parents.shrink_to_fit();
+        self.children.shrink_to_fit();
+        for children in self.children.values_mut() {
+            children.shrink_to_fit();
+        }
+        self.disabled.shrink_to_fit();
+        self.free.shrink_to_fit();
+        self.created.shrink_to_fit();
+        self.userdata.shrink_to_fit();
+        self.relations.shrink_to_fit();
+        self.slots.shrink_to_fit();
+        self.occupants.shrink_to_fit();
+        if let Some(ref mut queries) = self.queries {
+            queries.get_mut().clear();
+        }
+        if let Some(ref mut order) = self.order {
+            order.shrink_to_fit();
+        }
+    }
+    /// Check the system's internal invariants, returning a description of the first one found
+    /// broken. This is a self-check for tests and debug builds, e.g. after `from_parts` or
+    /// `set_boxed`.
+    ///
+    /// Checked are entity indices against the ID counter, the free list and the per-slot
+    /// generations, the per-type index and presence masks against the stored components, and
+    /// the hierarchy, enabled flags and creation order against the set of live entities.
+    pub fn validate(&self) -> Result<(), String> {
+        for (id, map) in &self.data {
+            if id.index() >= self.ids {
+                return Err(format!("entity {} is not below the ID counter {}", id, self.ids));
+            }
+            let mut mask = 0;
+            for (type_id, comp) in &map.map {
+                if (**comp).type_id() != *type_id {
+                    return Err(format!("entity {} has a component stored under the wrong type \
+                                        {:?}",
+                                       id,
+                                       type_id));
+                }
+                if !self.index.get(type_id).is_some_and(|ids| ids.contains(id)) {
+                    return Err(format!("entity {} is missing from the index of {:?}", id, type_id));
+                }
+                if let Some(bit) = self.bits.get(type_id) {
+                    mask |= 1 << bit;
+                }
+            }
+            if map.mask != mask {
+                return Err(format!("entity {} has a stale presence mask", id));
+            }
+        }
+        for (type_id, ids) in &self.index {
+            for id in ids {
+                if !self.data.get(id).is_some_and(|map| map.contains_type_id(type_id)) {
+                    return Err(format!("index of {:?} lists entity {} without one", type_id, id));
+                }
+            }
+        }
+        let related = self.relations
+            .values()
+            .flat_map(|targets| targets.iter())
+            .flat_map(|(from, ids)| std::iter::once(from).chain(ids));
+        let linked = self.parents.iter().flat_map(|(child, parent)| [child, parent]).chain(related);
+        let flagged = self.disabled.iter().chain(self.created.keys()).chain(self.userdata.keys());
+        if let Some(id) = linked.chain(flagged).find(|id| !self.exists(**id)) {
+            return Err(format!("bookkeeping refers to dead entity {}", id));
+        }
+        if let Some(ref order) = self.order {
+            let unique: HashSet<_> = order.iter().collect();
+            if order.len() != self.data.len() || unique.len() != order.len() ||
+               order.iter().any(|id| !self.exists(*id)) {
+                return Err("creation order doesn't match the live entities".to_string());
+            }
+        }
+        let mut live = HashSet::new();
+        for id in self.data.keys() {
+            if !live.insert(id.index()) {
+                return Err(format!("entity {} shares its index with another live entity", id));
+            }
+            if self.occupant(id.index()) != Some(*id) {
+                return Err(format!("entity {} is missing from the index occupants", id));
+            }
+        }
+        if self.occupants.len() != self.data.len() {
+            return Err("index occupants refer to dead entities".to_string());
+        }
+        if let Some(dead) = self.free.iter().find(|dead| live.contains(&dead.index())) {
+            return Err(format!("free list holds {}, whose index is in use", dead));
+        }
+        for id in self.data.keys().chain(&self.free) {
+            if self.slots.get(&id.index()).is_none_or(|&latest| latest < id.generation()) {
+                return Err(format!("slot of entity {} is behind its generation", id));
+            }
+        }
+        Ok(())
+    }
+    /// Remove every entity from the system, returning an iterator over their IDs and components.
+    ///
+    /// Dropping the iterator before it is exhausted still removes all remaining entities, so
+    /// the `Ecs` is always empty afterwards. The ID counter is not reset, so entities created
+    /// later won't reuse drained IDs.
+    ///
+    /// Observers registered with `on_destroy` are called for every entity before it is removed.
+    pub fn drain(&mut self) -> impl Iterator<Item = (EntityId, ComponentMap)> + '_ {
+        if !self.destroy_observers.is_empty() || self.events.is_some() {
+            let ids: Vec<_> = self.iter().collect();
+            for id in ids {
+                self.notify_destroy(id);
+            }
+        }
+        self.index.clear();
+        self.parents.clear();
+        self.children.clear();
+        self.relations.clear();
+        self.disabled.clear();
+        self.created.clear();
+        self.userdata.clear();
+        self.occupants.clear();
+        self.journal = None;
+        if let Some(ref mut stamps) = self.stamps {
+            stamps.clear();
+        }
+        self.generation += 1;
+        let ordered: Vec<_> = self.order
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|id| (id, self.data.remove(&id).expect("Ecs.drain: internal order error")))
+            .collect();
+        ordered.into_iter().chain(self.data.drain())
+    }
+    /// Exchange the entire contents of this system with `other`'s in constant time, e.g. to
+    /// ping-pong between a "current" and a "next" world in a double-buffered simulation.
+    ///
+    /// Everything moves along with the entities, including the ID counters, indices,
+    /// registered types and hooks, observers, resources and checkpoints, so each world stays
+    /// self-consistent. This is the same as `std::mem::swap(self, other)`.
+    pub fn swap_with(&mut self, other: &mut Ecs) {
+        mem::swap(self, other);
     }
     /// Collect all entity IDs into a vector (after emptying the vector).
     ///
@@ -302,16 +2228,420 @@ impl Ecs {
         dest.clear();
         dest.extend(self.iter());
     }
+    /// Collect all entity IDs into a vector (after emptying the vector), sorted in ascending
+    /// order.
+    ///
+    /// Unlike `collect`, the result doesn't depend on `HashMap` iteration order, which makes it
+    /// handy for deterministic comparisons in tests.
+    pub fn collect_sorted(&self, dest: &mut Vec<EntityId>) {
+        self.collect(dest);
+        dest.sort();
+    }
     /// Collect the IDs of all entities containing a certain set of component types into a vector.
     ///
     /// After calling this method, the vector `dest` will contain *only* those entities who have
     /// at least each type of component specified in the filter.
+    ///
+    /// Only the entities having the filter's rarest required component type are scanned, so a
+    /// filter including a rare type is fast even in a large system. (In `new_ordered` mode,
+    /// every entity is scanned to keep the result in creation order.)
     pub fn collect_with<'a>(&'a self, components: &'a ComponentFilter, dest: &mut Vec<EntityId>) {
-        let ids = self.data.keys().cloned();
         dest.clear();
-        dest.extend(ids.filter(|e| {
-            self.has_all(*e, components)
-                .expect("Ecs.collect_with: internal id filter error")
-        }))
+        self.append_with(components, dest);
+    }
+    /// Collect the IDs of all entities having every component type required by `require` and
+    /// none of those required by `exclude` into a vector (after emptying the vector), e.g. for
+    /// "alive, not frozen" queries.
+    pub fn collect_with_excluding(&self,
+                                  require: &ComponentFilter,
+                                  exclude: &ComponentFilter,
+                                  dest: &mut Vec<EntityId>) {
+        let mut filter = require.clone();
+        filter.excluded.extend(exclude.iter());
+        filter.names.extend(exclude.names.iter());
+        self.collect_with(&filter, dest);
+    }
+    /// Collect the IDs of all entities having every component type in `types` into a vector
+    /// (after emptying the vector), for dynamic code that holds `TypeId`s rather than a
+    /// `ComponentFilter`, e.g. from `ComponentMap::type_ids`.
+    pub fn collect_with_types(&self, types: &[TypeId], dest: &mut Vec<EntityId>) {
+        let mask = self.presence_mask(types.iter().cloned());
+        dest.clear();
+        dest.extend(self.candidates(types.iter().cloned())
+            .1
+            .filter(|id| Self::map_has_types(&self.data[id], types.iter().cloned(), mask)));
+    }
+    /// Like `collect_with`, but append the matching IDs to `dest` without emptying it first, e.g.
+    /// to build the union of several queries (followed by `dedup_ids`).
+    pub fn append_with(&self, components: &ComponentFilter, dest: &mut Vec<EntityId>) {
+        if !self.append_cached(components, dest) {
+            dest.extend(self.matching(components))
+        }
+    }
+    /// Sort the IDs in `dest` and remove repeated ones, e.g. after building the union of
+    /// overlapping queries with `append_with`. A single query never yields an ID twice.
+    pub fn dedup_ids(dest: &mut Vec<EntityId>) {
+        dest.sort();
+        dest.dedup();
+    }
+    /// Like `collect_with`, but skip entities disabled with `set_enabled`.
+    pub fn collect_enabled_with(&self, components: &ComponentFilter, dest: &mut Vec<EntityId>) {
+        dest.clear();
+        dest.extend(self.matching(components).filter(|id| !self.disabled.contains(id)))
+    }
+    /// Return `true` if at least one entity has every component type in the filter.
+    ///
+    /// Stops at the first matching entity instead of collecting them all.
+    pub fn exists_with(&self, components: &ComponentFilter) -> bool {
+        self.matching(components).next().is_some()
+    }
+    /// Like `collect_with`, but also return statistics about how selective the query was.
+    ///
+    /// This is diagnostic data for deciding which queries are worth caching or indexing.
+    pub fn collect_with_stats(&self,
+                              components: &ComponentFilter,
+                              dest: &mut Vec<EntityId>)
+                              -> QueryStats {
+        self.collect_with(components, dest);
+        QueryStats {
+            scanned: self.candidates(components.iter()).0,
+            matched: dest.len(),
+        }
+    }
+    /// Return the first entity for which `pred` returns `true`, or `None` if there is none.
+    ///
+    /// The predicate gets the `Ecs` so it can inspect any of the entity's components, which
+    /// makes this an escape hatch for value-based queries a `ComponentFilter` can't express.
+    /// "First" is in arbitrary iteration order.
+    pub fn find<F: Fn(EntityId, &Ecs) -> bool>(&self, pred: F) -> Option<EntityId> {
+        self.iter().find(|id| pred(*id, self))
+    }
+    /// Call `f` once for each unordered pair of distinct entities matching `filter`, e.g. for
+    /// collision checks. `f` gets the `Ecs` so it can read both entities' components.
+    ///
+    /// For `n` matching entities this makes `n * (n - 1) / 2` calls, so large groups are better
+    /// narrowed down first, e.g. with a spatial grid.
+    pub fn for_each_pair<F>(&self, filter: &ComponentFilter, mut f: F)
+        where F: FnMut(EntityId, EntityId, &Ecs)
+    {
+        let ids: Vec<_> = self.matching(filter).collect();
+        for (i, a) in ids.iter().enumerate() {
+            for b in &ids[i + 1..] {
+                f(*a, *b, self);
+            }
+        }
+    }
+    /// Return every entity for which `pred` returns `true`. See `find`.
+    pub fn find_all<F: Fn(EntityId, &Ecs) -> bool>(&self, pred: F) -> Vec<EntityId> {
+        self.iter().filter(|id| pred(*id, self)).collect()
+    }
+    /// Collect the IDs of all entities for which `pred` returns `true` into a vector (after
+    /// emptying the vector). The predicate gets each entity's components, so it can test their
+    /// values, not just their presence, e.g. to find entities whose health dropped to zero.
+    ///
+    /// This is the most general query, but it calls `pred` for every entity in the system; use
+    /// `collect_with` first to narrow down large systems by component type.
+    pub fn collect_where<F>(&self, pred: F, dest: &mut Vec<EntityId>)
+        where F: Fn(EntityId, &ComponentMap) -> bool
+    {
+        dest.clear();
+        dest.extend(self.entries().filter(|&(id, map)| pred(id, map)).map(|(id, _)| id));
+    }
+    /// Collect the IDs of all entities containing a certain set of component types into a set.
+    ///
+    /// Like `collect_with`, but fills a `HashSet` for fast membership tests. The set is emptied
+    /// first.
+    pub fn collect_with_set(&self, components: &ComponentFilter, dest: &mut HashSet<EntityId>) {
+        dest.clear();
+        dest.extend(self.matching(components))
+    }
+    fn matching<'a>(&'a self,
+                    components: &'a ComponentFilter)
+                    -> impl Iterator<Item = EntityId> + 'a {
+        let masks = self.filter_masks(components);
+        self.candidates(components.iter())
+            .1
+            .filter(move |id| Self::map_matches(&self.data[id], components, masks))
+    }
+    /// Return the number of entities worth scanning for entities having all the `required`
+    /// types and an iterator over them: those having the rarest one, or every entity if there
+    /// is none.
+    fn candidates<I>(&self, required: I) -> (usize, Box<dyn Iterator<Item = EntityId> + '_>)
+        where I: Iterator<Item = TypeId>
+    {
+        let count = |type_id: &TypeId| self.index.get(type_id).map_or(0, HashSet::len);
+        match required.min_by_key(count) {
+            Some(rarest) if self.order.is_none() => {
+                let ids = self.index.get(&rarest);
+                (count(&rarest), Box::new(ids.into_iter().flat_map(|ids| ids.iter().cloned())))
+            }
+            _ => (self.data.len(), self.iter()),
+        }
+    }
+    fn entries(&self) -> Box<dyn Iterator<Item = (EntityId, &ComponentMap)> + '_> {
+        match self.order {
+            Some(ref order) => Box::new(order.iter().map(move |id| (*id, &self.data[id]))),
+            None => Box::new(self.data.iter().map(|(id, map)| (*id, map))),
+        }
+    }
+    fn presence_mask<I: Iterator<Item = TypeId>>(&self, types: I) -> Option<PresenceMask> {
+        let mut types = types;
+        types.try_fold(0, |mask, type_id| self.bits.get(&type_id).map(|bit| mask | 1 << bit))
+    }
+    fn filter_masks(&self, filter: &ComponentFilter) -> FilterMasks {
+        FilterMasks {
+            required: self.presence_mask(filter.iter()),
+            excluded: self.presence_mask(filter.iter_excluded()),
+        }
+    }
+    fn map_has_types<I>(map: &ComponentMap, types: I, mask: Option<PresenceMask>) -> bool
+        where I: Iterator<Item = TypeId>
+    {
+        let mut types = types;
+        match mask {
+            Some(mask) => map.mask & mask == mask,
+            None => types.all(|type_id| map.contains_type_id(&type_id)),
+        }
+    }
+    fn map_matches(map: &ComponentMap, filter: &ComponentFilter, masks: FilterMasks) -> bool {
+        match masks {
+            FilterMasks { required: Some(required), excluded: Some(excluded) } => {
+                map.mask & required == required && map.mask & excluded == 0
+            }
+            _ => filter.matches(map),
+        }
+    }
+    fn insert_component(&mut self,
+                        id: EntityId,
+                        type_id: TypeId,
+                        comp: Box<dyn Any>)
+                        -> EcsResult<Option<Box<dyn Any>>> {
+        let (data, mut books) = self.split();
+        let map = data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok(books.store(id, map, type_id, comp).0)
+    }
+    fn remove_component(&mut self,
+                        id: EntityId,
+                        type_id: &TypeId)
+                        -> EcsResult<Option<Box<dyn Any>>> {
+        let map = self.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        let mut prev = map.map.remove(type_id);
+        if prev.is_some() {
+            map.signature.set(None);
+        }
+        if let Some(bit) = self.bits.get(type_id) {
+            map.mask &= !(1 << bit);
+        }
+        if let Some(ref mut comp) = prev {
+            self.record(EcsEvent::ComponentRemoved(id, *type_id), Some(&**comp));
+            self.cleanup(id, type_id, &mut **comp);
+            self.unindex(id, type_id);
+            self.log_event(EcsEvent::ComponentRemoved(id, *type_id));
+            self.generation += 1;
+        }
+        Ok(prev)
+    }
+    /// Remember the name of component type `C` for `component_type_names`.
+    pub(crate) fn name_type<C: Component>(&mut self) {
+        self.type_names.entry(TypeId::of::<C>()).or_insert_with(std::any::type_name::<C>);
+    }
+    fn replace_map(&mut self, id: EntityId, mut map: ComponentMap) -> ComponentMap {
+        self.type_names.extend(map.names.drain());
+        map.mask = map.type_ids()
+            .filter_map(|type_id| self.bits.get(&type_id))
+            .fold(0, |mask, bit| mask | 1 << bit);
+        for type_id in map.type_ids() {
+            self.index_component(id, type_id);
+        }
+        let old = self.data.insert(id, map).expect("Ecs.replace_map: internal entity error");
+        if self.journal.is_some() {
+            let new_types: Vec<_> = self.data[&id].type_ids().collect();
+            for type_id in new_types {
+                let prev = old.map.get(&type_id).map(|comp| &**comp);
+                self.record(EcsEvent::ComponentSet(id, type_id), prev);
+            }
+        }
+        for (type_id, comp) in &old.map {
+            if !self.data[&id].contains_type_id(type_id) {
+                self.unindex(id, type_id);
+                self.log_event(EcsEvent::ComponentRemoved(id, *type_id));
+                self.record(EcsEvent::ComponentRemoved(id, *type_id), Some(&**comp));
+            }
+        }
+        self.generation += 1;
+        old
+    }
+    /// Destroy the entity, returning its components without running their cleanup hooks, for
+    /// callers that hand the components back.
+    fn take_entity(&mut self, id: EntityId) -> EcsResult<ComponentMap> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        self.notify_destroy(id);
+        let map = self.data.remove(&id).expect("Ecs.destroy_entity: internal entity error");
+        self.occupants.remove(&id.index());
+        for (type_id, comp) in &map.map {
+            self.unindex(id, type_id);
+            self.record(EcsEvent::ComponentRemoved(id, *type_id), Some(&**comp));
+        }
+        let position = self.order.as_mut().map(|order| {
+            let pos = order.iter().position(|&other| other == id);
+            let pos = pos.expect("Ecs.destroy_entity: internal order error");
+            order.remove(pos);
+            pos
+        });
+        self.record_destroyed(id, position);
+        self.unlink(id);
+        self.unrelate_all(id);
+        self.disabled.remove(&id);
+        self.created.remove(&id);
+        self.userdata.remove(&id);
+        // retire the slot once its generations run out
+        if self.slots.get(&id.index()).is_none_or(|&latest| latest < u32::MAX) {
+            self.free.push(id);
+        }
+        self.generation += 1;
+        Ok(map)
+    }
+    fn notify_destroy(&mut self, id: EntityId) {
+        for observer in &mut self.destroy_observers {
+            observer(id);
+        }
+        self.log_event(EcsEvent::EntityDestroyed(id));
+    }
+    fn allocate_id(&mut self) -> EcsResult<EntityId> {
+        // `create_entity_at` prunes the free list, so no free index is in use. The slot's latest
+        // generation can be ahead of the dead ID's if an older generation was recreated
+        // directly, e.g. by `get_or_create_at`, so count up from the former.
+        if let Some(dead) = self.free.pop() {
+            let latest = self.slots.get(&dead.index()).cloned().unwrap_or(0);
+            return Ok(EntityId::from_index(dead.index(), latest.max(dead.generation()) + 1));
+        }
+        if self.ids > INDEX_MASK {
+            return Err(EcsError::Overflow);
+        }
+        let id = EntityId(self.ids);
+        self.ids += 1;
+        Ok(id)
+    }
+    /// Create the entity with the exact ID `id` if it doesn't exist, returning whether it was
+    /// created, or `EcsError::EntityNotFound` if another generation of its index is alive.
+    fn create_entity_at(&mut self, id: EntityId) -> EcsResult<bool> {
+        match self.occupant(id.index()) {
+            Some(live) if live == id => return Ok(false),
+            Some(_) => return Err(EcsError::EntityNotFound(id)),
+            None => {}
+        }
+        self.data.insert(id, Default::default());
+        self.occupants.insert(id.index(), id.generation());
+        if let Some(ref mut order) = self.order {
+            order.push(id);
+        }
+        // don't recycle an older generation of the same slot while `id` is alive
+        self.free.retain(|dead| dead.index() != id.index());
+        let slot = self.slots.entry(id.index()).or_insert(0);
+        *slot = (*slot).max(id.generation());
+        self.ids = self.ids.max(id.index() + 1);
+        self.generation += 1;
+        self.created.insert(id, self.generation);
+        self.log_event(EcsEvent::EntityCreated(id));
+        self.record(EcsEvent::EntityCreated(id), None);
+        Ok(true)
+    }
+    /// Return the live entity with the given index, if there is one.
+    fn occupant(&self, index: IdNumber) -> Option<EntityId> {
+        self.occupants.get(&index).map(|&generation| EntityId::from_index(index, generation))
+    }
+    fn stamp(&self, id: EntityId, type_id: TypeId) -> EcsResult<u64> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        self.stamps
+            .as_ref()
+            .and_then(|stamps| stamps.get(&(id, type_id)).cloned())
+            .ok_or(EcsError::ComponentNotFound(type_id))
+    }
+    fn index_component(&mut self, id: EntityId, type_id: TypeId) {
+        self.split().1.index(id, type_id);
+    }
+    /// Borrow the entity store separately from the bookkeeping for changes to it, so components
+    /// can be stored and accounted for with a single entity lookup.
+    fn split(&mut self) -> (&mut hash::EntityMap<ComponentMap>, Books<'_>) {
+        let books = Books {
+            bits: &self.bits,
+            cloners: &self.cloners,
+            index: &mut self.index,
+            stamps: &mut self.stamps,
+            tick: self.tick,
+            events: &mut self.events,
+            journal: &mut self.journal,
+            generation: &mut self.generation,
+        };
+        (&mut self.data, books)
+    }
+    fn unindex(&mut self, id: EntityId, type_id: &TypeId) {
+        if let Some(ref mut stamps) = self.stamps {
+            stamps.remove(&(id, *type_id));
+        }
+        let now_empty = match self.index.get_mut(type_id) {
+            Some(ids) => {
+                ids.remove(&id);
+                ids.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            self.index.remove(type_id);
+        }
+    }
+}
+
+/// The bookkeeping that has to follow every component stored in an `Ecs`, borrowed apart from
+/// its entity store by `Ecs::split`.
+struct Books<'a> {
+    bits: &'a HashMap<TypeId, u32>,
+    cloners: &'a HashMap<TypeId, CloneFn>,
+    index: &'a mut HashMap<TypeId, HashSet<EntityId>>,
+    stamps: &'a mut Option<HashMap<(EntityId, TypeId), u64>>,
+    tick: u64,
+    events: &'a mut Option<Vec<EcsEvent>>,
+    journal: &'a mut Option<undo::Journal>,
+    generation: &'a mut u64,
+}
+
+impl<'a> Books<'a> {
+    /// Store `comp` as the component of type `type_id` in `map`, the components of entity `id`,
+    /// and account for it. Return the previous component, if any, along with the stored one.
+    fn store<'m>(&mut self,
+                 id: EntityId,
+                 map: &'m mut ComponentMap,
+                 type_id: TypeId,
+                 comp: Box<dyn Any>)
+                 -> (Option<Box<dyn Any>>, &'m mut Box<dyn Any>) {
+        if let Some(bit) = self.bits.get(&type_id) {
+            map.mask |= 1 << bit;
+        }
+        let (prev, slot) = match map.map.entry(type_id) {
+            hash_map::Entry::Occupied(entry) => {
+                let slot = entry.into_mut();
+                (Some(mem::replace(slot, comp)), slot)
+            }
+            hash_map::Entry::Vacant(entry) => {
+                map.signature.set(None);
+                (None, entry.insert(comp))
+            }
+        };
+        self.index(id, type_id);
+        self.record(EcsEvent::ComponentSet(id, type_id), prev.as_deref());
+        *self.generation += 1;
+        (prev, slot)
+    }
+    /// Add entity `id` to the index of `type_id`, stamp the component and log the change.
+    fn index(&mut self, id: EntityId, type_id: TypeId) {
+        self.log(EcsEvent::ComponentSet(id, type_id));
+        self.index.entry(type_id).or_default().insert(id);
+        if let Some(ref mut stamps) = *self.stamps {
+            stamps.insert((id, type_id), self.tick);
+        }
     }
 }
@@ -0,0 +1,78 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use {clone_component, CloneFn, Component, ComponentMap, Ecs, EcsError, EcsResult, EntityId};
+
+/// A template of components that can be stamped out into new entities with
+/// `Ecs::spawn_prefab`.
+///
+/// Prefab components must be `Clone`, since every spawned entity gets its own independent copy.
+#[derive(Default)]
+pub struct Prefab {
+    components: ComponentMap,
+    cloners: HashMap<TypeId, CloneFn>,
+    names: HashMap<TypeId, &'static str>,
+}
+
+impl Prefab {
+    /// Create a new prefab without components.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Add a component of type `C` to the prefab, returning the previous one if there was one.
+    pub fn add<C: Component + Clone>(&mut self, comp: C) -> Option<C> {
+        self.cloners.insert(TypeId::of::<C>(), clone_component::<C>);
+        self.names.insert(TypeId::of::<C>(), std::any::type_name::<C>());
+        self.components.set(comp)
+    }
+    /// Add a component of type `C` to the prefab and return the prefab, for chaining.
+    pub fn with<C: Component + Clone>(mut self, comp: C) -> Self {
+        self.add(comp);
+        self
+    }
+    /// Return the prefab's components.
+    pub fn components(&self) -> &ComponentMap {
+        &self.components
+    }
+    pub(crate) fn clone_components(&self) -> ComponentMap {
+        self.components.clone_with(&self.cloners)
+    }
+}
+
+impl Ecs {
+    /// Create a new entity with a copy of each of the prefab's components, and return its ID.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `create_entity` if the limit set with `with_max_entities` was reached, or if
+    /// the system ran out of entity indices.
+    pub fn spawn_prefab(&mut self, prefab: &Prefab) -> EntityId {
+        let id = self.create_entity();
+        self.type_names.extend(&prefab.names);
+        for (type_id, comp) in prefab.clone_components().map {
+            self.insert_component(id, type_id, comp)
+                .expect("Ecs.spawn_prefab: internal entity error");
+        }
+        id
+    }
+    /// Copy each of the prefab's components onto the existing entity `id`, e.g. to layer a
+    /// variant prefab over a base one. Components the entity already has are replaced if
+    /// `overwrite` is true and kept otherwise.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist.
+    pub fn apply_prefab(&mut self,
+                        id: EntityId,
+                        prefab: &Prefab,
+                        overwrite: bool)
+                        -> EcsResult<()> {
+        if !self.exists(id) {
+            return Err(EcsError::EntityNotFound(id));
+        }
+        self.type_names.extend(&prefab.names);
+        for (type_id, comp) in prefab.clone_components().map {
+            if overwrite || !self.data[&id].contains_type_id(&type_id) {
+                self.insert_component(id, type_id, comp)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use {Component, ComponentFilter, Ecs, EcsResult, EntityId};
+
+/// Fluent query over the entities of an `Ecs`, created by `Ecs::query`.
+///
+/// A `Query` bundles a `ComponentFilter` with a borrow of the system, so the usual
+/// create-filter-then-collect steps become a single chain:
+///
+/// ```
+/// use recs::Ecs;
+///
+/// struct Alive;
+/// struct Frozen;
+///
+/// let mut ecs = Ecs::new();
+/// let walker = ecs.create_entity();
+/// let statue = ecs.create_entity();
+/// let _ = ecs.set(walker, Alive);
+/// let _ = ecs.set(statue, Alive);
+/// let _ = ecs.set(statue, Frozen);
+/// let ids: Vec<_> = ecs.query().with::<Alive>().without::<Frozen>().iter().collect();
+/// assert_eq!(ids, vec![walker]);
+/// ```
+#[derive(Clone)]
+pub struct Query<'a> {
+    ecs: &'a Ecs,
+    filter: ComponentFilter,
+}
+
+impl<'a> Query<'a> {
+    /// Require matching entities to have a component of type `C`.
+    pub fn with<C: Component>(mut self) -> Self {
+        self.filter.add::<C>();
+        self
+    }
+    /// Require matching entities not to have a component of type `C`.
+    pub fn without<C: Component>(mut self) -> Self {
+        self.filter.exclude::<C>();
+        self
+    }
+    /// Return the filter built up so far.
+    pub fn filter(&self) -> &ComponentFilter {
+        &self.filter
+    }
+    /// Return an iterator over the IDs of all matching entities.
+    pub fn iter(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.ecs.matching(&self.filter)
+    }
+    /// Return an iterator over all matching entities that have a component of type `C`, paired
+    /// with a shared reference to it.
+    pub fn iter_component<C: Component>(&self) -> impl Iterator<Item = (EntityId, &'a C)> + '_ {
+        let ecs = self.ecs;
+        self.iter().filter_map(move |id| ecs.borrow(id).ok().map(|comp| (id, comp)))
+    }
+    /// Collect the IDs of all matching entities into a vector (after emptying the vector).
+    pub fn collect(&self, dest: &mut Vec<EntityId>) {
+        self.ecs.collect_with(&self.filter, dest);
+    }
+    /// Return the number of matching entities.
+    pub fn count(&self) -> usize {
+        self.iter().count()
+    }
+    /// Return `true` if the entity `id` matches the query, or `EcsError::EntityNotFound` if the
+    /// entity does not exist.
+    pub fn matches(&self, id: EntityId) -> EcsResult<bool> {
+        self.ecs.has_all(id, &self.filter)
+    }
+}
+
+impl Ecs {
+    /// Start a fluent query over the system's entities. With no `with` or `without` calls, the
+    /// query matches every entity.
+    pub fn query(&self) -> Query<'_> {
+        Query {
+            ecs: self,
+            filter: ComponentFilter::new(),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
+use {Ecs, EcsError, EcsResult, EntityId};
+
+/// Every typed relation in the system, keyed by marker type, then by source entity.
+pub(crate) type Relations = HashMap<TypeId, HashMap<EntityId, HashSet<EntityId>>>;
+
+impl Ecs {
+    /// Relate `from` to `to` with the relation marked by type `R`, e.g.
+    /// `ecs.relate::<Targets>(archer, goblin)`. Relations are directed and independent of the
+    /// parent/child hierarchy; relating two entities again has no effect.
+    ///
+    /// When either entity is destroyed, all of its relations are removed with it. Return
+    /// `EcsError::EntityNotFound` if either entity does not exist.
+    pub fn relate<R: 'static>(&mut self, from: EntityId, to: EntityId) -> EcsResult<()> {
+        for id in &[from, to] {
+            if !self.exists(*id) {
+                return Err(EcsError::EntityNotFound(*id));
+            }
+        }
+        self.relations
+            .entry(TypeId::of::<R>())
+            .or_default()
+            .entry(from)
+            .or_default()
+            .insert(to);
+        Ok(())
+    }
+    /// Remove the relation of type `R` from `from` to `to`, returning whether it existed.
+    pub fn unrelate<R: 'static>(&mut self, from: EntityId, to: EntityId) -> bool {
+        let type_id = TypeId::of::<R>();
+        let targets = match self.relations.get_mut(&type_id) {
+            Some(targets) => targets,
+            None => return false,
+        };
+        let removed = targets.get_mut(&from).is_some_and(|ids| ids.remove(&to));
+        if targets.get(&from).is_some_and(HashSet::is_empty) {
+            targets.remove(&from);
+        }
+        if targets.is_empty() {
+            self.relations.remove(&type_id);
+        }
+        removed
+    }
+    /// Return every entity that `from` is related to with the relation of type `R`, sorted by
+    /// ID.
+    pub fn related<R: 'static>(&self, from: EntityId) -> Vec<EntityId> {
+        let mut ids: Vec<_> = self.relations
+            .get(&TypeId::of::<R>())
+            .and_then(|targets| targets.get(&from))
+            .map_or_else(Vec::new, |ids| ids.iter().cloned().collect());
+        ids.sort();
+        ids
+    }
+    /// Return every entity related to `to` with the relation of type `R`, sorted by ID.
+    ///
+    /// Only forward links are stored, so this takes time proportional to the number of
+    /// entities with a relation of type `R`.
+    pub fn relations_to<R: 'static>(&self, to: EntityId) -> Vec<EntityId> {
+        let mut ids: Vec<_> = self.relations
+            .get(&TypeId::of::<R>())
+            .into_iter()
+            .flat_map(|targets| targets.iter())
+            .filter(|&(_, ids)| ids.contains(&to))
+            .map(|(from, _)| *from)
+            .collect();
+        ids.sort();
+        ids
+    }
+    /// Remove every relation of any type from or to `id`.
+    pub(crate) fn unrelate_all(&mut self, id: EntityId) {
+        for targets in self.relations.values_mut() {
+            targets.remove(&id);
+            for ids in targets.values_mut() {
+                ids.remove(&id);
+            }
+            targets.retain(|_, ids| !ids.is_empty());
+        }
+        self.relations.retain(|_, targets| !targets.is_empty());
+    }
+}
@@ -0,0 +1,44 @@
+use std::any::{Any, TypeId};
+use {Component, Ecs, EntityId};
+
+pub(crate) type RemapFn = fn(&mut dyn Any, &dyn Fn(EntityId) -> EntityId);
+
+/// Trait for component types that store `EntityId`s referring to other entities.
+///
+/// Components are opaque to the `Ecs`, so after bulk operations that renumber entities (e.g.
+/// loading or merging worlds), stored references would dangle. Implementing this trait and
+/// registering the type with `Ecs::register_entity_refs` lets `Ecs::remap_entity_refs` rewrite
+/// them.
+pub trait EntityReferencing {
+    /// Replace every `EntityId` stored in `self` with `map(id)`.
+    fn map_entities(&mut self, map: &dyn Fn(EntityId) -> EntityId);
+}
+
+fn remap_component<C: Component + EntityReferencing>(comp: &mut dyn Any,
+                                                     map: &dyn Fn(EntityId) -> EntityId) {
+    comp.downcast_mut::<C>()
+        .expect("remap_component: internal downcast error")
+        .map_entities(map)
+}
+
+impl Ecs {
+    /// Register component type `C` as holding entity references, so that `remap_entity_refs`
+    /// rewrites them.
+    pub fn register_entity_refs<C: Component + EntityReferencing>(&mut self) {
+        self.remappers.insert(TypeId::of::<C>(), remap_component::<C>);
+    }
+    /// Rewrite the entity references stored in every component whose type was registered with
+    /// `register_entity_refs`, replacing each `id` with `map(id)`.
+    ///
+    /// Entity IDs themselves are not changed; this only fixes up references held by
+    /// components.
+    pub fn remap_entity_refs<F: Fn(EntityId) -> EntityId>(&mut self, map: F) {
+        for comps in self.data.values_mut() {
+            for (type_id, comp) in &mut comps.map {
+                if let Some(remap) = self.remappers.get(type_id) {
+                    remap(&mut **comp, &map);
+                }
+            }
+        }
+    }
+}
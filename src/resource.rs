@@ -0,0 +1,28 @@
+use {Component, Ecs};
+
+impl Ecs {
+    /// Store `res` as the system's resource of type `R`, returning the previous one if there
+    /// was one.
+    ///
+    /// Resources are singletons that belong to the system rather than to any entity, e.g. the
+    /// game clock or an event queue. Like components, resource types must be registered with
+    /// `register_clone` if the system is cloned.
+    pub fn insert_resource<R: Component>(&mut self, res: R) -> Option<R> {
+        self.resources.set(res)
+    }
+    /// Return a shared reference to the system's resource of type `R`, or `None` if none is
+    /// stored.
+    pub fn resource<R: Component>(&self) -> Option<&R> {
+        self.resources.borrow().ok()
+    }
+    /// Return a mutable reference to the system's resource of type `R`, or `None` if none is
+    /// stored.
+    pub fn resource_mut<R: Component>(&mut self) -> Option<&mut R> {
+        self.resources.borrow_mut().ok()
+    }
+    /// Remove the system's resource of type `R` and return it by value, e.g. to take ownership
+    /// of an event queue and drain it. Return `None` if none is stored.
+    pub fn take_resource<R: Component>(&mut self) -> Option<R> {
+        self.resources.remove()
+    }
+}
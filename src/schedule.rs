@@ -0,0 +1,49 @@
+use Ecs;
+
+/// A unit of game logic that runs against an `Ecs` once per `Scheduler` tick.
+///
+/// `System` is implemented for all `FnMut(&mut Ecs)` closures, so simple systems don't need a
+/// dedicated type.
+pub trait System {
+    /// Run this system once against the provided ECS.
+    fn run(&mut self, ecs: &mut Ecs);
+}
+
+impl<F: FnMut(&mut Ecs)> System for F {
+    fn run(&mut self, ecs: &mut Ecs) {
+        self(ecs)
+    }
+}
+
+/// Ordered list of systems that are run one after another on each `tick`.
+///
+/// Systems run in the order they were added. Nothing is run in parallel.
+#[derive(Default)]
+pub struct Scheduler {
+    systems: Vec<Box<dyn System>>,
+}
+
+impl Scheduler {
+    /// Create a new scheduler without any systems.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Add a system to the end of the schedule.
+    pub fn add<S: System + 'static>(&mut self, system: S) {
+        self.systems.push(Box::new(system));
+    }
+    /// Return the number of systems in the schedule.
+    pub fn len(&self) -> usize {
+        self.systems.len()
+    }
+    /// Return `true` if the schedule contains no systems.
+    pub fn is_empty(&self) -> bool {
+        self.systems.is_empty()
+    }
+    /// Run every system once, in insertion order.
+    pub fn tick(&mut self, ecs: &mut Ecs) {
+        for system in &mut self.systems {
+            system.run(ecs);
+        }
+    }
+}
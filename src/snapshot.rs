@@ -0,0 +1,87 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use {Component, Ecs, EntityId};
+
+pub(crate) type SendFn = fn(&dyn Any) -> Box<dyn Any + Send>;
+
+fn send_component<C: Component + Clone + Send>(comp: &dyn Any) -> Box<dyn Any + Send> {
+    Box::new(comp.downcast_ref::<C>().expect("send_component: internal downcast error").clone())
+}
+
+/// An owned copy of an `Ecs` that can be moved to another thread, e.g. to serialize an autosave
+/// in the background while the game keeps running.
+///
+/// Returned by `Ecs::clone_sendable`. Only component types registered with
+/// `Ecs::register_send` are copied; the types of any others are listed by `skipped`.
+#[derive(Default)]
+pub struct SendableSnapshot {
+    entities: HashMap<EntityId, HashMap<TypeId, Box<dyn Any + Send>>>,
+    skipped: Vec<TypeId>,
+}
+
+impl SendableSnapshot {
+    /// Return the number of entities in the snapshot.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+    /// Return `true` if the snapshot has no entities.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+    /// Return an iterator over the IDs of the entities in the snapshot.
+    pub fn ids(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.entities.keys().cloned()
+    }
+    /// Return a shared reference to the copied component of type `C` of the specified entity,
+    /// or `None` if the entity or component isn't in the snapshot.
+    pub fn borrow<C: Component>(&self, id: EntityId) -> Option<&C> {
+        self.entities
+            .get(&id)
+            .and_then(|comps| comps.get(&TypeId::of::<C>()))
+            .and_then(|comp| comp.downcast_ref())
+    }
+    /// Return an iterator over the copied components of the specified entity, keyed by type.
+    pub fn components(&self,
+                      id: EntityId)
+                      -> impl Iterator<Item = (TypeId, &(dyn Any + Send))> + '_ {
+        self.entities
+            .get(&id)
+            .into_iter()
+            .flat_map(|comps| comps.iter().map(|(type_id, comp)| (*type_id, &**comp)))
+    }
+    /// Return the component types that were present in the system but left out of the snapshot
+    /// because they weren't registered with `Ecs::register_send`, sorted and without duplicates.
+    pub fn skipped(&self) -> &[TypeId] {
+        &self.skipped
+    }
+}
+
+impl Ecs {
+    /// Register component type `C` as copyable into a `SendableSnapshot`.
+    pub fn register_send<C: Component + Clone + Send>(&mut self) {
+        self.senders.insert(TypeId::of::<C>(), send_component::<C>);
+    }
+    /// Copy every entity and its registered components into an owned snapshot that is `Send`,
+    /// so slow work like serialization can happen on another thread.
+    ///
+    /// Components whose type wasn't registered with `register_send` are left out, and their
+    /// types recorded in `SendableSnapshot::skipped`.
+    pub fn clone_sendable(&self) -> SendableSnapshot {
+        let mut snapshot = SendableSnapshot::default();
+        for (id, map) in &self.data {
+            let mut comps = HashMap::new();
+            for (type_id, comp) in &map.map {
+                match self.senders.get(type_id) {
+                    Some(send) => {
+                        comps.insert(*type_id, send(&**comp));
+                    }
+                    None => snapshot.skipped.push(*type_id),
+                }
+            }
+            snapshot.entities.insert(*id, comps);
+        }
+        snapshot.skipped.sort();
+        snapshot.skipped.dedup();
+        snapshot
+    }
+}
@@ -0,0 +1,133 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use {Component, ComponentFilter, EcsError, EcsResult, EntityId};
+
+type SyncComponentMap = HashMap<TypeId, Box<dyn Any + Send + Sync>>;
+
+#[derive(Default)]
+struct SyncData {
+    ids: u64,
+    data: HashMap<EntityId, SyncComponentMap>,
+}
+
+/// Thread-safe entity-component system that can be shared between threads, e.g. in an `Arc`.
+///
+/// `SyncEcs` offers the core `Ecs` operations through `&self`. The whole world sits behind a
+/// single `RwLock`: lookups take a read lock, while creating and destroying entities and setting
+/// or removing components take a write lock. Because the world must be `Sync`, components have
+/// to be `Send + Sync`.
+///
+/// References can't escape the lock, so instead of `borrow`/`borrow_mut` there are `read` and
+/// `write`, which run a closure on the component while the lock is held.
+#[derive(Default)]
+pub struct SyncEcs {
+    inner: RwLock<SyncData>,
+}
+
+impl SyncEcs {
+    /// Create a new and empty thread-safe ECS.
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Create a new entity without components and return its ID.
+    pub fn create_entity(&self) -> EntityId {
+        let mut inner = self.write_lock();
+        let id = EntityId(inner.ids);
+        inner.ids += 1;
+        inner.data.insert(id, Default::default());
+        id
+    }
+    /// Return `true` if the provided entity exists in the system.
+    pub fn exists(&self, id: EntityId) -> bool {
+        self.read_lock().data.contains_key(&id)
+    }
+    /// Destroy the provided entity, automatically removing any of its components.
+    ///
+    /// Return `EcsError::EntityNotFound` if the entity does not exist or was already deleted.
+    pub fn destroy_entity(&self, id: EntityId) -> EcsResult<()> {
+        self.write_lock().data.remove(&id).map(|_| ()).ok_or(EcsError::EntityNotFound(id))
+    }
+    /// For the specified entity, add a component of type `C` to the system, returning the
+    /// previous one if there was one. See `Ecs::set`.
+    pub fn set<C>(&self, id: EntityId, comp: C) -> EcsResult<Option<C>>
+        where C: Component + Send + Sync
+    {
+        let mut inner = self.write_lock();
+        let map = inner.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok(map.insert(TypeId::of::<C>(), Box::new(comp)).map(|old| {
+            *old.downcast().expect("SyncEcs.set: internal downcast error")
+        }))
+    }
+    /// Remove the specified entity's component of type `C`, returning it if there was one. See
+    /// `Ecs::remove`.
+    pub fn remove<C>(&self, id: EntityId) -> EcsResult<Option<C>>
+        where C: Component + Send + Sync
+    {
+        let mut inner = self.write_lock();
+        let map = inner.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok(map.remove(&TypeId::of::<C>()).map(|old| {
+            *old.downcast().expect("SyncEcs.remove: internal downcast error")
+        }))
+    }
+    /// Return `true` if the specified entity has a component of type `C`. See `Ecs::has`.
+    pub fn has<C: Component>(&self, id: EntityId) -> EcsResult<bool> {
+        let inner = self.read_lock();
+        let map = inner.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        Ok(map.contains_key(&TypeId::of::<C>()))
+    }
+    /// Return a clone of the requested entity's component of type `C`. See `Ecs::get`.
+    pub fn get<C>(&self, id: EntityId) -> EcsResult<C>
+        where C: Component + Clone + Send + Sync
+    {
+        self.read(id, C::clone)
+    }
+    /// Run `f` on a shared reference to the requested entity's component of type `C` while
+    /// holding a read lock, and return its result.
+    pub fn read<C, R, F>(&self, id: EntityId, f: F) -> EcsResult<R>
+        where C: Component + Send + Sync,
+              F: FnOnce(&C) -> R
+    {
+        let inner = self.read_lock();
+        let map = inner.data.get(&id).ok_or(EcsError::EntityNotFound(id))?;
+        map.get(&TypeId::of::<C>())
+           .map(|c| f(c.downcast_ref().expect("SyncEcs.read: internal downcast error")))
+           .ok_or_else(|| EcsError::ComponentNotFound(TypeId::of::<C>()))
+    }
+    /// Run `f` on a mutable reference to the requested entity's component of type `C` while
+    /// holding a write lock, and return its result.
+    pub fn write<C, R, F>(&self, id: EntityId, f: F) -> EcsResult<R>
+        where C: Component + Send + Sync,
+              F: FnOnce(&mut C) -> R
+    {
+        let mut inner = self.write_lock();
+        let map = inner.data.get_mut(&id).ok_or(EcsError::EntityNotFound(id))?;
+        map.get_mut(&TypeId::of::<C>())
+           .map(|c| f(c.downcast_mut().expect("SyncEcs.write: internal downcast error")))
+           .ok_or_else(|| EcsError::ComponentNotFound(TypeId::of::<C>()))
+    }
+    /// Collect all entity IDs into a vector (after emptying the vector).
+    pub fn collect(&self, dest: &mut Vec<EntityId>) {
+        dest.clear();
+        dest.extend(self.read_lock().data.keys().cloned());
+    }
+    /// Collect the IDs of all entities containing a certain set of component types into a
+    /// vector (after emptying the vector). See `Ecs::collect_with`.
+    pub fn collect_with(&self, components: &ComponentFilter, dest: &mut Vec<EntityId>) {
+        dest.clear();
+        let inner = self.read_lock();
+        dest.extend(inner.data
+                         .iter()
+                         .filter(|&(_, map)| {
+                             components.iter().all(|t| map.contains_key(&t)) &&
+                             !components.iter_excluded().any(|t| map.contains_key(&t))
+                         })
+                         .map(|(id, _)| *id));
+    }
+    fn read_lock(&self) -> RwLockReadGuard<'_, SyncData> {
+        self.inner.read().expect("SyncEcs: lock poisoned")
+    }
+    fn write_lock(&self) -> RwLockWriteGuard<'_, SyncData> {
+        self.inner.write().expect("SyncEcs: lock poisoned")
+    }
+}
@@ -0,0 +1,77 @@
+use std::any::{Any, TypeId};
+use std::rc::Rc;
+use {Component, Ecs, EntityId};
+
+type Upcast<T> = Rc<dyn Fn(&dyn Any) -> &T>;
+
+struct TraitCasts<T: ?Sized> {
+    casts: Vec<(TypeId, Upcast<T>)>,
+}
+
+impl<T: ?Sized> Clone for TraitCasts<T> {
+    fn clone(&self) -> Self {
+        TraitCasts { casts: self.casts.clone() }
+    }
+}
+
+impl Ecs {
+    /// Register concrete component type `C` as queryable through the trait object type `T`.
+    ///
+    /// `upcast` converts a `&C` to a `&T`; for a trait object type, a closure like `|c| c` is
+    /// enough. Once registered, `C` components show up in `collect_trait::<T>()`.
+    ///
+    /// ```
+    /// # use recs::Ecs;
+    /// trait Drawable {
+    ///     fn draw(&self) -> String;
+    /// }
+    /// struct Circle;
+    /// impl Drawable for Circle {
+    ///     fn draw(&self) -> String {
+    ///         "circle".to_string()
+    ///     }
+    /// }
+    ///
+    /// let mut ecs = Ecs::new();
+    /// ecs.register_trait::<dyn Drawable, Circle>(|c| c);
+    /// let id = ecs.create_entity();
+    /// let _ = ecs.set(id, Circle);
+    /// let drawn: Vec<_> = ecs.collect_trait::<dyn Drawable>()
+    ///                        .into_iter()
+    ///                        .map(|(_, d)| d.draw())
+    ///                        .collect();
+    /// assert_eq!(drawn, vec!["circle"]);
+    /// ```
+    pub fn register_trait<T: ?Sized + 'static, C: Component>(&mut self, upcast: fn(&C) -> &T) {
+        let trait_id = TypeId::of::<T>();
+        let mut casts = self.trait_casts
+            .get(&trait_id)
+            .and_then(|casts| casts.downcast_ref::<TraitCasts<T>>())
+            .cloned()
+            .unwrap_or(TraitCasts { casts: Vec::new() });
+        casts.casts.retain(|&(type_id, _)| type_id != TypeId::of::<C>());
+        let cast: Upcast<T> = Rc::new(move |comp: &dyn Any| {
+            upcast(comp.downcast_ref::<C>().expect("Ecs.register_trait: internal downcast error"))
+        });
+        casts.casts.push((TypeId::of::<C>(), cast));
+        self.trait_casts.insert(trait_id, Rc::new(casts));
+    }
+    /// Return every component registered as queryable through `T` with `register_trait`, as a
+    /// `&T`, paired with the ID of the entity it belongs to.
+    pub fn collect_trait<T: ?Sized + 'static>(&self) -> Vec<(EntityId, &T)> {
+        let casts = match self.trait_casts
+            .get(&TypeId::of::<T>())
+            .and_then(|casts| casts.downcast_ref::<TraitCasts<T>>()) {
+            Some(casts) => casts,
+            None => return Vec::new(),
+        };
+        let mut found = Vec::new();
+        for (type_id, cast) in &casts.casts {
+            for id in self.index.get(type_id).into_iter().flat_map(|ids| ids.iter()) {
+                let comp = &self.data[id].map[type_id];
+                found.push((*id, cast(&**comp)));
+            }
+        }
+        found
+    }
+}
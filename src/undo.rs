@@ -0,0 +1,179 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use {Books, CloneFn, Ecs, EcsError, EcsEvent, EcsResult, EntityId};
+
+static NEXT_CHECKPOINT: AtomicUsize = AtomicUsize::new(0);
+
+/// A point in an `Ecs`'s history that it can be rolled back to, as returned by
+/// `Ecs::checkpoint`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    id: usize,
+    position: usize,
+}
+
+/// A structural change recorded in the journal.
+struct Entry {
+    event: EcsEvent,
+    /// A copy of the component value the change replaced or removed, if any.
+    old: Option<Box<dyn Any>>,
+    /// Where a destroyed entity was in the creation order, for systems created with
+    /// `Ecs::new_ordered`.
+    position: Option<usize>,
+}
+
+/// The structural changes made since the first checkpoint.
+pub(crate) struct Journal {
+    entries: Vec<Entry>,
+    /// The checkpoints that can still be rolled back to, oldest first.
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Journal {
+    pub(crate) fn clone_with(&self, cloners: &HashMap<TypeId, CloneFn>) -> Journal {
+        let entries = self.entries
+            .iter()
+            .map(|entry| {
+                Entry {
+                    event: entry.event,
+                    old: entry.old.as_ref().map(|comp| cloners[&(**comp).type_id()](&**comp)),
+                    position: entry.position,
+                }
+            })
+            .collect();
+        Journal {
+            entries,
+            checkpoints: self.checkpoints.clone(),
+        }
+    }
+}
+
+impl Ecs {
+    /// Mark the current state of the system so it can be restored with `rollback_to`, e.g. for
+    /// undo in an editor or a turn-based game.
+    ///
+    /// From the first checkpoint on, the system keeps a journal of its structural changes
+    /// along with a copy of every component replaced or removed, including all components of
+    /// destroyed entities. Every component type changed while checkpoints are in use must
+    /// therefore be registered with `register_clone`, or the change panics. The journal grows
+    /// until `clear_checkpoints` is called.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        let journal = self.journal.get_or_insert_with(|| {
+            Journal {
+                entries: Vec::new(),
+                checkpoints: Vec::new(),
+            }
+        });
+        let checkpoint = Checkpoint {
+            id: NEXT_CHECKPOINT.fetch_add(1, Ordering::Relaxed),
+            position: journal.entries.len(),
+        };
+        journal.checkpoints.push(checkpoint);
+        checkpoint
+    }
+    /// Undo every structural change made since `checkpoint` was taken, newest first: entities
+    /// created since are destroyed, destroyed ones are recreated with their old IDs, and
+    /// components are put back the way they were. The checkpoint stays valid, so it can be
+    /// rolled back to again, while checkpoints taken after it are invalidated.
+    ///
+    /// Rolling back is not itself a change to observe: observers registered with `on_destroy`
+    /// aren't called and nothing is added to the event log.
+    ///
+    /// Only the changes counted by `generation` are undone. Components modified in place, e.g.
+    /// with `borrow_mut`, keep their current value, and the hierarchy links, relations, enabled
+    /// flags and user data of destroyed entities aren't restored.
+    ///
+    /// Return `EcsError::InvalidCheckpoint` if `checkpoint` was invalidated, the journal was
+    /// reset by `clear_checkpoints` or `drain` since it was taken, or it belongs to another
+    /// system.
+    pub fn rollback_to(&mut self, checkpoint: &Checkpoint) -> EcsResult<()> {
+        let mut journal = self.journal.take().ok_or(EcsError::InvalidCheckpoint)?;
+        let live = match journal.checkpoints.binary_search_by_key(&checkpoint.id, |c| c.id) {
+            Ok(pos) => pos,
+            Err(_) => {
+                self.journal = Some(journal);
+                return Err(EcsError::InvalidCheckpoint);
+            }
+        };
+        journal.checkpoints.truncate(live + 1);
+        // with the journal, event log and observers taken out, undoing goes unnoticed
+        let events = self.events.take();
+        let observers = mem::take(&mut self.destroy_observers);
+        for entry in journal.entries.drain(checkpoint.position..).rev() {
+            let undone = match (entry.event, entry.old) {
+                (EcsEvent::EntityCreated(id), _) => self.take_entity(id).map(|_| ()),
+                (EcsEvent::EntityDestroyed(id), _) => {
+                    self.restore_entity(id, entry.position);
+                    Ok(())
+                }
+                (EcsEvent::ComponentSet(id, type_id), None) => {
+                    self.remove_component(id, &type_id).map(|_| ())
+                }
+                (EcsEvent::ComponentSet(id, type_id), Some(old)) |
+                (EcsEvent::ComponentRemoved(id, type_id), Some(old)) => {
+                    self.insert_component(id, type_id, old).map(|_| ())
+                }
+                (EcsEvent::ComponentRemoved(..), None) => Ok(()),
+            };
+            undone.expect("Ecs.rollback_to: internal journal error");
+        }
+        self.events = events;
+        self.destroy_observers = observers;
+        self.journal = Some(journal);
+        Ok(())
+    }
+    /// Stop journaling changes and drop the journal, invalidating every checkpoint taken so
+    /// far, e.g. once a turn is committed and can no longer be undone.
+    pub fn clear_checkpoints(&mut self) {
+        self.journal = None;
+    }
+    /// Like `Books::record`, for callers that hold the whole system.
+    pub(crate) fn record(&mut self, event: EcsEvent, old: Option<&dyn Any>) {
+        self.split().1.record(event, old);
+    }
+    /// Record the destruction of `id` in the journal if checkpoints are in use, along with its
+    /// `position` in the creation order.
+    pub(crate) fn record_destroyed(&mut self, id: EntityId, position: Option<usize>) {
+        if let Some(ref mut journal) = self.journal {
+            journal.entries.push(Entry {
+                event: EcsEvent::EntityDestroyed(id),
+                old: None,
+                position,
+            });
+        }
+    }
+    /// Recreate the destroyed entity `id` without components, back at `position` in the
+    /// creation order.
+    fn restore_entity(&mut self, id: EntityId, position: Option<usize>) {
+        self.create_entity_at(id).expect("Ecs.rollback_to: internal entity error");
+        if let (Some(order), Some(position)) = (self.order.as_mut(), position) {
+            order.pop();
+            order.insert(position, id);
+        }
+    }
+}
+
+impl<'a> Books<'a> {
+    /// Record `event` in the journal if checkpoints are in use, with a copy of `old`, the
+    /// component it replaced or removed.
+    pub(crate) fn record(&mut self, event: EcsEvent, old: Option<&dyn Any>) {
+        let cloners = self.cloners;
+        if let Some(ref mut journal) = *self.journal {
+            let old = old.map(|comp| {
+                let clone = cloners.get(&comp.type_id()).unwrap_or_else(|| {
+                    panic!("Ecs.checkpoint: component type {:?} was not registered with \
+                            Ecs.register_clone",
+                           comp.type_id())
+                });
+                clone(comp)
+            });
+            journal.entries.push(Entry {
+                event,
+                old,
+                position: None,
+            });
+        }
+    }
+}
@@ -1,6 +1,5 @@
 use std::ops::Add;
 use std::collections::{HashMap, HashSet};
-#[macro_use]
 extern crate recs;
 use recs::*;
 
@@ -12,7 +11,7 @@ struct Vector2f {
 
 impl Vector2f {
     fn new(x: f32, y: f32) -> Self {
-        Vector2f { x: x, y: y }
+        Vector2f { x, y }
     }
     fn new_i64(x: i64, y: i64) -> Self {
         Self::new(x as f32, y as f32)
@@ -108,3 +107,2257 @@ fn test_collect() {
         assert_eq!(Ok(target_pos), system.get(id));
     }
 }
+
+#[test]
+fn test_component_index() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let c = ecs.create_entity();
+    let _ = ecs.set(a, Position(Vector2f::new(1., 1.)));
+    let _ = ecs.set(b, Position(Vector2f::new(2., 2.)));
+    let _ = ecs.set(b, Velocity(Vector2f::new(0., 0.)));
+    let _ = ecs.set(c, Velocity(Vector2f::new(0., 0.)));
+    assert_eq!(ecs.count::<Position>(), 2);
+    assert_eq!(ecs.count::<Velocity>(), 2);
+    assert_eq!(ecs.remove::<Position>(a), Ok(Some(Position(Vector2f::new(1., 1.)))));
+    assert_eq!(ecs.remove::<Position>(a), Ok(None));
+    assert_eq!(ecs.iter_component::<Position>().collect::<Vec<_>>(),
+               vec![(b, &Position(Vector2f::new(2., 2.)))]);
+    let _ = ecs.destroy_entity(b);
+    assert_eq!(ecs.count::<Position>(), 0);
+    assert_eq!(ecs.iter_component::<Velocity>().map(|(id, _)| id).collect::<Vec<_>>(),
+               vec![c]);
+}
+
+#[test]
+fn test_id_offset() {
+    let mut first = Ecs::new();
+    let mut second = Ecs::with_id_offset(1_000);
+    let a = first.create_entity();
+    let b = second.create_entity();
+    let c = second.create_entity();
+    assert!(a != b && b != c);
+    assert!(first.exists(a) && !first.exists(b));
+    assert!(second.exists(b) && second.exists(c) && !second.exists(a));
+}
+
+#[test]
+fn test_not_found_error() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.destroy_entity(id);
+    let err: Box<dyn std::error::Error> = Box::new(ecs.get::<Position>(id).unwrap_err());
+    assert_eq!(err.to_string(), "entity 0 not found");
+}
+
+#[test]
+fn test_update_by_value() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.update(id, |p: Position| p),
+               Err(EcsError::ComponentNotFound(std::any::TypeId::of::<Position>())));
+    let _ = ecs.set(id, Position(Vector2f::new(1., 2.)));
+    assert_eq!(ecs.update(id, |p: Position| Position(p.0 + Vector2f::new(1., 1.))), Ok(()));
+    assert_eq!(ecs.get(id), Ok(Position(Vector2f::new(2., 3.))));
+    assert_eq!(ecs.count::<Position>(), 1);
+}
+
+struct Movement;
+
+impl System for Movement {
+    fn run(&mut self, ecs: &mut Ecs) {
+        let mut ids = Vec::new();
+        ecs.collect_with(&component_filter!(Position, Velocity), &mut ids);
+        for id in ids {
+            let pos: Position = ecs.get(id).unwrap();
+            let vel: Velocity = ecs.get(id).unwrap();
+            let _ = ecs.set(id, update_position(&pos, &vel));
+        }
+    }
+}
+
+#[test]
+fn test_scheduler() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(id, Velocity(Vector2f::new(1., 0.)));
+    let mut order = Vec::new();
+    let mut schedule = Scheduler::new();
+    schedule.add(Movement);
+    schedule.add(move |ecs: &mut Ecs| {
+        order.push(ecs.get::<Position>(id).unwrap().0.x);
+        assert_eq!(order.len() as f32, order[order.len() - 1]);
+    });
+    assert_eq!(schedule.len(), 2);
+    schedule.tick(&mut ecs);
+    schedule.tick(&mut ecs);
+    assert_eq!(ecs.get(id), Ok(Position(Vector2f::new(2., 0.))));
+}
+
+#[test]
+fn test_collect_with_set() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(b, Velocity(Vector2f::new(0., 0.)));
+    let mut set = HashSet::new();
+    set.insert(b);
+    ecs.collect_with_set(&component_filter!(Position), &mut set);
+    assert_eq!(set, [a].iter().cloned().collect());
+}
+
+#[test]
+fn test_next_id() {
+    let mut ecs = Ecs::with_id_offset(10);
+    assert_eq!(ecs.next_id(), 10);
+    let id = ecs.create_entity();
+    let _ = ecs.create_entity();
+    let _ = ecs.destroy_entity(id);
+    assert_eq!(ecs.next_id(), 12);
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Acceleration(Vector2f);
+
+#[test]
+fn test_borrow_mut_tuples() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(id, Velocity(Vector2f::new(1., 0.)));
+    assert_eq!(ecs.borrow_mut_three::<Position, Velocity, Acceleration>(id).err(),
+               Some(EcsError::ComponentNotFound(std::any::TypeId::of::<Acceleration>())));
+    let _ = ecs.set(id, Acceleration(Vector2f::new(0., 1.)));
+    {
+        let (pos, vel, acc) = ecs.borrow_mut_three::<Position, Velocity, Acceleration>(id)
+            .unwrap();
+        vel.0 = vel.0 + acc.0;
+        pos.0 = pos.0 + vel.0;
+    }
+    assert_eq!(ecs.get(id), Ok(Position(Vector2f::new(1., 1.))));
+    assert_eq!(ecs.borrow_mut_two::<Velocity, Velocity>(id).err(),
+               Some(EcsError::Aliased(std::any::TypeId::of::<Velocity>())));
+}
+
+#[test]
+fn test_iter_component_mut() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..5).map(|_| ecs.create_entity()).collect();
+    for (i, id) in ids.iter().enumerate() {
+        if i % 2 == 0 {
+            let _ = ecs.set(*id, Position(Vector2f::new_i64(i as i64, 0)));
+        }
+    }
+    for (_, pos) in ecs.iter_component_mut::<Position>() {
+        pos.0.y = 1.;
+    }
+    assert_eq!(ecs.iter_component_mut::<Position>().count(), 3);
+    assert_eq!(ecs.get(ids[4]), Ok(Position(Vector2f::new(4., 1.))));
+    assert_eq!(ecs.has::<Position>(ids[1]), Ok(false));
+}
+
+#[test]
+fn test_drain() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..4).map(|_| ecs.create_entity()).collect();
+    for id in &ids {
+        let _ = ecs.set(*id, Position(Vector2f::new(0., 0.)));
+    }
+    let drained: HashSet<_> = ecs.drain().map(|(id, _)| id).collect();
+    assert_eq!(drained, ids.iter().cloned().collect());
+    assert_eq!(ecs.iter().count(), 0);
+    let more: Vec<_> = (0..4).map(|_| ecs.create_entity()).collect();
+    assert!(more.iter().all(|id| !ids.contains(id)));
+    let _ = ecs.set(more[0], Position(Vector2f::new(0., 0.)));
+    // dropping the iterator early still empties the system
+    assert_eq!(ecs.drain().take(1).count(), 1);
+    assert_eq!(ecs.iter().count(), 0);
+    assert_eq!(ecs.count::<Position>(), 0);
+}
+
+#[test]
+fn test_presence_bits() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Position(Vector2f::new(0., 0.)));
+    assert_eq!(ecs.register_presence_bit::<Position>(), Some(0));
+    assert_eq!(ecs.register_presence_bit::<Velocity>(), Some(1));
+    assert_eq!(ecs.register_presence_bit::<Position>(), Some(0));
+    let _ = ecs.set(a, Velocity(Vector2f::new(0., 0.)));
+    let _ = ecs.set(b, Velocity(Vector2f::new(0., 0.)));
+    let both = component_filter!(Position, Velocity);
+    let mixed = component_filter!(Position, Acceleration);
+    assert_eq!(ecs.has_all(a, &both), Ok(true));
+    assert_eq!(ecs.has_all(b, &both), Ok(false));
+    assert_eq!(ecs.has_any(b, &both), Ok(true));
+    assert_eq!(ecs.has_any(b, &mixed), Ok(false));
+    let _ = ecs.remove::<Velocity>(a);
+    assert_eq!(ecs.has_all(a, &both), Ok(false));
+    assert_eq!(ecs.has_any(a, &mixed), Ok(true));
+    let mut ids = Vec::new();
+    ecs.collect_with(&component_filter!(Velocity), &mut ids);
+    assert_eq!(ids, vec![b]);
+}
+
+#[test]
+fn test_get_multiple() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let pos = Position(Vector2f::new(1., 2.));
+    let vel = Velocity(Vector2f::new(3., 4.));
+    let _ = ecs.set(id, pos);
+    let _ = ecs.set(id, vel);
+    assert_eq!(ecs.get2::<Position, Velocity>(id), Ok((pos, vel)));
+    assert_eq!(ecs.get3::<Position, Velocity, Acceleration>(id),
+               Err(EcsError::ComponentNotFound(std::any::TypeId::of::<Acceleration>())));
+}
+
+#[test]
+fn test_hierarchy() {
+    let mut ecs = Ecs::new();
+    let root = ecs.create_entity();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let leaf = ecs.create_entity();
+    assert_eq!(ecs.set_parent(a, root), Ok(()));
+    assert_eq!(ecs.set_parent(b, root), Ok(()));
+    assert_eq!(ecs.set_parent(leaf, a), Ok(()));
+    assert_eq!(ecs.children(root), Ok(vec![a, b]));
+    assert_eq!(ecs.parent(leaf), Ok(Some(a)));
+    assert_eq!(ecs.set_parent(root, leaf), Err(EcsError::Cycle(root)));
+    assert_eq!(ecs.set_parent(a, a), Err(EcsError::Cycle(a)));
+    // reparenting moves the child
+    assert_eq!(ecs.set_parent(leaf, b), Ok(()));
+    assert_eq!(ecs.children(a), Ok(vec![]));
+    assert_eq!(ecs.children(b), Ok(vec![leaf]));
+    // destroying without cascading orphans the children
+    let _ = ecs.destroy_entity(b);
+    assert_eq!(ecs.parent(leaf), Ok(None));
+    assert_eq!(ecs.children(root), Ok(vec![a]));
+    // cascading destroys every descendant
+    let _ = ecs.set_parent(leaf, a);
+    assert_eq!(ecs.destroy_entity_hierarchy(root, true), Ok(()));
+    assert!(!ecs.exists(a) && !ecs.exists(leaf));
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Owner(EntityId);
+
+#[test]
+fn test_destroy_where() {
+    let mut ecs = Ecs::new();
+    let root = ecs.create_entity();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let unrelated = ecs.create_entity();
+    let _ = ecs.set(a, Owner(root));
+    let _ = ecs.set(b, Owner(a));
+    // a cycle must not loop forever
+    let _ = ecs.set(root, Owner(b));
+    let owned_by = |ecs: &Ecs, id, parent| ecs.get::<Owner>(id) == Ok(Owner(parent));
+    assert_eq!(ecs.destroy_where(root, owned_by), Ok(3));
+    assert_eq!(ecs.iter().collect::<Vec<_>>(), vec![unrelated]);
+    assert_eq!(ecs.destroy_where(root, owned_by), Err(EcsError::EntityNotFound(root)));
+}
+
+#[test]
+fn test_any() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert!(!ecs.any::<Position>());
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    assert!(ecs.any::<Position>() && !ecs.any::<Velocity>());
+    let _ = ecs.remove::<Position>(id);
+    assert!(!ecs.any::<Position>());
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+struct Score(u32);
+
+#[test]
+fn test_get_or_default() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.get_or_default::<Score>(id), Ok(Score(0)));
+    let _ = ecs.set(id, Score(7));
+    assert_eq!(ecs.get_or_default::<Score>(id), Ok(Score(7)));
+    let _ = ecs.destroy_entity(id);
+    assert_eq!(ecs.get_or_default::<Score>(id), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_clone() {
+    let mut ecs = Ecs::new();
+    ecs.register_clone::<Position>();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Position(Vector2f::new(1., 1.)));
+    let _ = ecs.set_parent(b, a);
+    let mut fork = ecs.clone();
+    let _ = fork.set(a, Position(Vector2f::new(2., 2.)));
+    assert_eq!(ecs.get(a), Ok(Position(Vector2f::new(1., 1.))));
+    assert_eq!(fork.get(a), Ok(Position(Vector2f::new(2., 2.))));
+    assert_eq!(fork.parent(b), Ok(Some(a)));
+    assert_eq!(fork.next_id(), ecs.next_id());
+    assert_eq!(fork.count::<Position>(), 1);
+}
+
+#[test]
+#[should_panic(expected = "register_clone")]
+fn test_clone_unregistered() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let _ = ecs.set(a, Velocity(Vector2f::new(1., 1.)));
+    let _ = ecs.clone();
+}
+
+#[test]
+fn test_diff() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    ecs.register_clone::<Position>();
+    ecs.register_clone::<Velocity>();
+    ecs.register_eq::<Position>();
+    let moved = ecs.create_entity();
+    let doomed = ecs.create_entity();
+    let still = ecs.create_entity();
+    let _ = ecs.set(moved, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(moved, Velocity(Vector2f::new(0., 0.)));
+    let _ = ecs.set(still, Velocity(Vector2f::new(0., 0.)));
+    let previous = ecs.clone();
+    assert!(ecs.diff(&previous).is_empty());
+
+    let _ = ecs.set(moved, Position(Vector2f::new(1., 0.)));
+    // Velocity isn't registered with register_eq, so value changes go unnoticed
+    let _ = ecs.set(moved, Velocity(Vector2f::new(1., 0.)));
+    let _ = ecs.remove::<Velocity>(still);
+    let _ = ecs.destroy_entity(doomed);
+    let born = ecs.create_entity();
+    let _ = ecs.set(born, Position(Vector2f::new(0., 0.)));
+
+    let diff = ecs.diff(&previous);
+    assert_eq!(diff.added_entities, vec![born]);
+    assert_eq!(diff.removed_entities, vec![doomed]);
+    assert_eq!(diff.entities[&moved],
+               EntityDiff { changed: vec![TypeId::of::<Position>()], ..Default::default() });
+    assert_eq!(diff.entities[&still],
+               EntityDiff { removed: vec![TypeId::of::<Velocity>()], ..Default::default() });
+    assert_eq!(diff.entities[&born],
+               EntityDiff { added: vec![TypeId::of::<Position>()], ..Default::default() });
+    assert_eq!(diff.entities.len(), 3);
+}
+
+#[test]
+fn test_apply_diff() {
+    let mut ecs = Ecs::new();
+    ecs.register_clone::<Position>();
+    ecs.register_eq::<Position>();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Position(Vector2f::new(0., 0.)));
+    let mut replica = ecs.clone();
+    // diffing against an identical clone changes nothing
+    assert_eq!(replica.apply_diff(&ecs.diff(&replica)), Ok(()));
+    assert!(ecs.diff(&replica).is_empty());
+
+    let _ = ecs.set(a, Position(Vector2f::new(1., 1.)));
+    let _ = ecs.destroy_entity(b);
+    let c = ecs.create_entity();
+    let _ = ecs.set(c, Position(Vector2f::new(2., 2.)));
+    assert_eq!(replica.apply_diff(&ecs.diff(&replica)), Ok(()));
+    assert!(ecs.diff(&replica).is_empty());
+    assert_eq!(replica.get(c), Ok(Position(Vector2f::new(2., 2.))));
+    assert!(!replica.exists(b));
+    assert_eq!(replica.next_id(), ecs.next_id());
+
+    // values of unregistered types can't be replayed
+    let _ = ecs.set(c, Velocity(Vector2f::new(0., 0.)));
+    assert_eq!(replica.apply_diff(&ecs.diff(&replica)),
+               Err(EcsError::ComponentNotFound(std::any::TypeId::of::<Velocity>())));
+    assert_eq!(replica.has::<Velocity>(c), Ok(false));
+}
+
+#[test]
+fn test_set_boxed() {
+    use std::any::{Any, TypeId};
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let boxed: Box<dyn Any> = Box::new(Position(Vector2f::new(1., 2.)));
+    assert!(ecs.set_boxed(id, TypeId::of::<Position>(), boxed).unwrap().is_none());
+    assert_eq!(ecs.get(id), Ok(Position(Vector2f::new(1., 2.))));
+    assert_eq!(ecs.count::<Position>(), 1);
+    let origin = Box::new(Position(Vector2f::new(0., 0.)));
+    let prev = ecs.set_boxed(id, TypeId::of::<Position>(), origin).unwrap().unwrap();
+    assert_eq!(prev.downcast_ref(), Some(&Position(Vector2f::new(1., 2.))));
+    assert_eq!(ecs.component_type_names(id), Ok(vec!["<unknown>"]));
+    // registering the type also records its name
+    ecs.register_clone::<Position>();
+    assert!(ecs.component_type_names(id).unwrap()[0].ends_with("Position"));
+}
+
+#[test]
+fn test_set_boxed_mismatch() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.set_boxed(id, TypeId::of::<Velocity>(), Box::new(Score(1))).err(),
+               Some(EcsError::TypeMismatch {
+                   expected: TypeId::of::<Velocity>(),
+                   found: TypeId::of::<Score>(),
+               }));
+    assert_eq!(ecs.has::<Velocity>(id), Ok(false));
+}
+
+#[test]
+fn test_entry() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    ecs.entry::<Score>(id).unwrap().and_modify(|s| s.0 += 1).or_insert(Score(10));
+    assert_eq!(ecs.get(id), Ok(Score(10)));
+    ecs.entry::<Score>(id).unwrap().and_modify(|s| s.0 += 1).or_insert(Score(10));
+    assert_eq!(ecs.get(id), Ok(Score(11)));
+    match ecs.entry::<Score>(id).unwrap() {
+        Entry::Occupied(entry) => assert_eq!(entry.remove(), Score(11)),
+        Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    assert_eq!(ecs.count::<Score>(), 0);
+    let _ = ecs.destroy_entity(id);
+    assert!(ecs.entry::<Score>(id).is_err());
+}
+
+#[test]
+fn test_query_cache() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let _ = ecs.set(a, Position(Vector2f::new(0., 0.)));
+    let filter = component_filter!(Position);
+    let mut cache = QueryCache::new();
+    assert_eq!(cache.get(&ecs, &filter), &[a]);
+    // modifying a component in place isn't a structural change
+    ecs.borrow_mut::<Position>(a).unwrap().0.x = 1.;
+    assert_eq!(cache.get(&ecs, &filter), &[a]);
+    let b = ecs.create_entity();
+    let _ = ecs.set(b, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.remove::<Position>(a);
+    assert_eq!(cache.get(&ecs, &filter), &[b]);
+    assert_eq!(cache.get(&ecs, &component_filter!(Velocity)), &[] as &[EntityId]);
+}
+
+#[test]
+fn test_generation() {
+    let mut ecs = Ecs::new();
+    let mut last = ecs.generation();
+    let mut bumped = |ecs: &Ecs| {
+        let changed = ecs.generation() > last;
+        last = ecs.generation();
+        changed
+    };
+    let id = ecs.create_entity();
+    assert!(bumped(&ecs));
+    let _ = ecs.set(id, Score(1));
+    assert!(bumped(&ecs));
+    let _ = ecs.set(id, Score(2));
+    assert!(bumped(&ecs));
+    ecs.borrow_mut::<Score>(id).unwrap().0 = 3;
+    let _ = ecs.update(id, |s: Score| Score(s.0 + 1));
+    let _ = ecs.get::<Score>(id);
+    assert!(!bumped(&ecs));
+    let _ = ecs.remove::<Velocity>(id);
+    assert!(!bumped(&ecs));
+    let _ = ecs.remove::<Score>(id);
+    assert!(bumped(&ecs));
+    let _ = ecs.destroy_entity(id);
+    assert!(bumped(&ecs));
+    let _ = ecs.destroy_entity(id);
+    assert!(!bumped(&ecs));
+}
+
+#[test]
+fn test_exists_with() {
+    let mut ecs = Ecs::new();
+    let door = ecs.create_entity();
+    let _ = ecs.set(door, Position(Vector2f::new(0., 0.)));
+    assert!(!ecs.exists_with(&component_filter!(Position, Velocity)));
+    let _ = ecs.set(door, Velocity(Vector2f::new(0., 0.)));
+    assert!(ecs.exists_with(&component_filter!(Position, Velocity)));
+}
+
+#[test]
+fn test_remove_by_type() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(3));
+    let removed = ecs.remove_by_type(id, TypeId::of::<Score>()).unwrap().unwrap();
+    assert_eq!(removed.downcast_ref(), Some(&Score(3)));
+    assert!(ecs.remove_by_type(id, TypeId::of::<Score>()).unwrap().is_none());
+    assert!(!ecs.any::<Score>());
+}
+
+#[test]
+fn test_collect_sorted() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..50).map(|_| ecs.create_entity()).collect();
+    let _ = ecs.destroy_entity(ids[10]);
+    let mut sorted = Vec::new();
+    ecs.collect_sorted(&mut sorted);
+    let expected: Vec<_> = ids.iter().cloned().filter(|id| *id != ids[10]).collect();
+    assert_eq!(sorted, expected);
+}
+
+#[test]
+fn test_filter_from_iter() {
+    use std::any::TypeId;
+    let mut filter: ComponentFilter = vec![TypeId::of::<Position>()].into_iter().collect();
+    filter.extend(Some(TypeId::of::<Velocity>()));
+    assert_eq!(filter, component_filter!(Position, Velocity));
+}
+
+impl EntityReferencing for Owner {
+    fn map_entities(&mut self, map: &dyn Fn(EntityId) -> EntityId) {
+        self.0 = map(self.0);
+    }
+}
+
+#[test]
+fn test_remap_entity_refs() {
+    let mut ecs = Ecs::new();
+    ecs.register_entity_refs::<Owner>();
+    let old = ecs.create_entity();
+    let new = ecs.create_entity();
+    let pet = ecs.create_entity();
+    let _ = ecs.set(pet, Owner(old));
+    ecs.remap_entity_refs(|id| if id == old { new } else { id });
+    assert_eq!(ecs.get(pet), Ok(Owner(new)));
+}
+
+#[test]
+fn test_take() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(5));
+    let mut score: Score = ecs.take(id).unwrap();
+    assert_eq!(ecs.take::<Score>(id),
+               Err(EcsError::ComponentNotFound(std::any::TypeId::of::<Score>())));
+    score.0 += 1;
+    let _ = ecs.set(id, score);
+    assert_eq!(ecs.get(id), Ok(Score(6)));
+}
+
+#[test]
+fn test_sync_ecs() {
+    use std::sync::Arc;
+    use std::thread;
+    let ecs = Arc::new(SyncEcs::new());
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(0));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let ecs = ecs.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    ecs.write(id, |s: &mut Score| s.0 += 1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(ecs.get(id), Ok(Score(400)));
+    assert_eq!(ecs.read(id, |s: &Score| s.0 * 2), Ok(800));
+    let mut ids = Vec::new();
+    ecs.collect_with(&component_filter!(Score), &mut ids);
+    assert_eq!(ids, vec![id]);
+    assert_eq!(ecs.remove::<Score>(id), Ok(Some(Score(400))));
+    assert_eq!(ecs.has::<Score>(id), Ok(false));
+    assert_eq!(ecs.destroy_entity(id), Ok(()));
+    assert!(!ecs.exists(id));
+}
+
+#[test]
+fn test_collect_with_stats() {
+    let mut ecs = Ecs::new();
+    for i in 0..8 {
+        let id = ecs.create_entity();
+        if i % 4 == 0 {
+            let _ = ecs.set(id, Score(i));
+        }
+    }
+    let mut ids = Vec::new();
+    // only the entities with the rarest required type are scanned
+    let stats = ecs.collect_with_stats(&component_filter!(Score), &mut ids);
+    assert_eq!(stats, QueryStats { scanned: 2, matched: 2 });
+    let stats = ecs.collect_with_stats(&component_filter!(Position), &mut ids);
+    assert_eq!(stats, QueryStats { scanned: 0, matched: 0 });
+    let stats = ecs.collect_with_stats(&ComponentFilter::new(), &mut ids);
+    assert_eq!(stats, QueryStats { scanned: 8, matched: 8 });
+    let mut ordered = Ecs::new_ordered();
+    for _ in 0..8 {
+        let id = ordered.create_entity();
+        let _ = ordered.set(id, Position(Vector2f::new(0., 0.)));
+    }
+    let first = ordered.iter().next().unwrap();
+    let _ = ordered.set(first, Score(0));
+    let stats = ordered.collect_with_stats(&component_filter!(Score, Position), &mut ids);
+    assert_eq!(stats, QueryStats { scanned: 8, matched: 1 });
+    assert_eq!(stats.selectivity(), 0.125);
+    assert_eq!(QueryStats::default().selectivity(), 0.0);
+}
+
+#[test]
+fn test_collect_with_rarest() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..6).map(|_| ecs.create_entity()).collect();
+    for id in &ids {
+        let _ = ecs.set(*id, Position(Vector2f::new(0., 0.)));
+    }
+    let _ = ecs.set(ids[1], Score(1));
+    let _ = ecs.set(ids[4], Score(4));
+    let _ = ecs.remove::<Position>(ids[4]);
+    let mut found = Vec::new();
+    ecs.collect_with(&component_filter!(Position, Score), &mut found);
+    assert_eq!(found, vec![ids[1]]);
+}
+
+#[test]
+fn test_find() {
+    let mut ecs = Ecs::new();
+    let weak = ecs.create_entity();
+    let strong = ecs.create_entity();
+    let _ = ecs.set(weak, Score(3));
+    let _ = ecs.set(strong, Score(30));
+    let low = |id, ecs: &Ecs| ecs.borrow::<Score>(id).map(|s| s.0 < 10) == Ok(true);
+    assert_eq!(ecs.find(low), Some(weak));
+    assert_eq!(ecs.find_all(|id, ecs| ecs.has::<Score>(id) == Ok(true)).len(), 2);
+    assert_eq!(ecs.find(|_, _| false), None);
+}
+
+#[test]
+fn test_set_many() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let gone = ecs.create_entity();
+    let _ = ecs.destroy_entity(gone);
+    let _ = ecs.set(b, Score(1));
+    assert_eq!(ecs.set_many(&[a, gone, b], Score(100)),
+               vec![Ok(None), Err(EcsError::EntityNotFound(gone)), Ok(Some(Score(1)))]);
+    assert_eq!(ecs.get(a), Ok(Score(100)));
+    assert_eq!(ecs.get(b), Ok(Score(100)));
+}
+
+trait Describe {
+    fn describe(&self) -> String;
+}
+
+impl Describe for Score {
+    fn describe(&self) -> String {
+        format!("score {}", self.0)
+    }
+}
+
+impl Describe for Position {
+    fn describe(&self) -> String {
+        format!("at {}, {}", (self.0).x, (self.0).y)
+    }
+}
+
+#[test]
+fn test_collect_trait() {
+    let mut ecs = Ecs::new();
+    assert!(ecs.collect_trait::<dyn Describe>().is_empty());
+    ecs.register_trait::<dyn Describe, Score>(|c| c);
+    ecs.register_trait::<dyn Describe, Position>(|c| c);
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(4));
+    let _ = ecs.set(a, Velocity(Vector2f::new(0., 0.)));
+    let _ = ecs.set(b, Position(Vector2f::new(1., 2.)));
+    let mut described: Vec<_> = ecs.collect_trait::<dyn Describe>()
+        .into_iter()
+        .map(|(id, d)| (id, d.describe()))
+        .collect();
+    described.sort();
+    assert_eq!(described,
+               vec![(a, "score 4".to_string()), (b, "at 1, 2".to_string())]);
+}
+
+#[test]
+fn test_try_borrow() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.try_get::<Score>(id), None);
+    let _ = ecs.set(id, Score(2));
+    assert_eq!(ecs.try_borrow::<Score>(id), Some(&Score(2)));
+    let _ = ecs.destroy_entity(id);
+    assert_eq!(ecs.try_get::<Score>(id), None);
+}
+
+#[test]
+fn test_enabled() {
+    let mut ecs = Ecs::new();
+    let shown = ecs.create_entity();
+    let hidden = ecs.create_entity();
+    let _ = ecs.set_many(&[shown, hidden], Score(0));
+    assert_eq!(ecs.set_enabled(hidden, false), Ok(()));
+    assert_eq!(ecs.is_enabled(hidden), Ok(false));
+    let filter = component_filter!(Score);
+    let mut ids = Vec::new();
+    ecs.collect_enabled_with(&filter, &mut ids);
+    assert_eq!(ids, vec![shown]);
+    ecs.collect_with(&filter, &mut ids);
+    assert_eq!(ids.len(), 2);
+    let _ = ecs.set_enabled(hidden, true);
+    ecs.collect_enabled_with(&filter, &mut ids);
+    assert_eq!(ids.len(), 2);
+    let _ = ecs.destroy_entity(hidden);
+    assert_eq!(ecs.set_enabled(hidden, false), Err(EcsError::EntityNotFound(hidden)));
+}
+
+#[test]
+fn test_component_map() {
+    let mut map = ComponentMap::new();
+    assert!(map.is_empty());
+    assert_eq!(map.set(Score(1)), None);
+    assert_eq!(map.set(Score(2)), Some(Score(1)));
+    map.borrow_mut::<Score>().unwrap().0 += 1;
+    assert_eq!(map.get::<Score>(), Ok(Score(3)));
+    assert!(map.contains::<Score>() && !map.contains::<Position>());
+    assert_eq!(map.type_ids().collect::<Vec<_>>(), vec![std::any::TypeId::of::<Score>()]);
+    assert_eq!(map.remove::<Score>(), Some(Score(3)));
+    assert_eq!(map.len(), 0);
+}
+
+#[test]
+fn test_spawn_prefab() {
+    let mut ecs = Ecs::new();
+    let goblin = Prefab::new().with(Score(10)).with(Position(Vector2f::new(0., 0.)));
+    assert!(goblin.components().contains::<Score>());
+    let a = ecs.spawn_prefab(&goblin);
+    let b = ecs.spawn_prefab(&goblin);
+    ecs.borrow_mut::<Score>(a).unwrap().0 = 0;
+    assert_eq!(ecs.get(b), Ok(Score(10)));
+    assert_eq!(ecs.get(a), Ok(Score(0)));
+    assert_eq!(goblin.components().get::<Score>(), Ok(Score(10)));
+    assert_eq!(ecs.count::<Position>(), 2);
+    assert!(ecs.component_type_names(a).unwrap()[1].ends_with("Score"));
+}
+
+#[test]
+fn test_with() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let _ = ecs.create_entity();
+    let _ = ecs.set(a, Score(1));
+    assert_eq!(ecs.with::<Score>().collect::<Vec<_>>(), vec![a]);
+    assert_eq!(ecs.with::<Position>().count(), 0);
+}
+
+#[test]
+fn test_set_components() {
+    let mut ecs = Ecs::new();
+    ecs.register_presence_bit::<Position>();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    let _ = ecs.set(id, Velocity(Vector2f::new(0., 0.)));
+    let mut map = ComponentMap::new();
+    map.set(Score(2));
+    map.set(Position(Vector2f::new(0., 0.)));
+    let old = ecs.set_components(id, map).unwrap();
+    assert_eq!(old.get::<Score>(), Ok(Score(1)));
+    assert_eq!(ecs.get(id), Ok(Score(2)));
+    assert_eq!((ecs.count::<Score>(), ecs.count::<Velocity>()), (1, 0));
+    assert!(ecs.exists_with(&component_filter!(Position)));
+    let _ = ecs.destroy_entity(id);
+    assert!(ecs.set_components(id, ComponentMap::new()).is_err());
+}
+
+#[test]
+fn test_excluding_filter() {
+    let mut ecs = Ecs::new();
+    let moving = ecs.create_entity();
+    let frozen = ecs.create_entity();
+    let _ = ecs.set_many(&[moving, frozen], Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(frozen, Score(0));
+    let filter = component_filter_excluding!(Position; Score);
+    let mut ids = Vec::new();
+    ecs.collect_with(&filter, &mut ids);
+    assert_eq!(ids, vec![moving]);
+    // the bitmask path must agree with the hash path
+    ecs.register_presence_bit::<Position>();
+    ecs.register_presence_bit::<Score>();
+    assert_eq!(ecs.has_all(moving, &filter), Ok(true));
+    assert_eq!(ecs.has_all(frozen, &filter), Ok(false));
+    let mut manual = ComponentFilter::new();
+    manual.add::<Position>();
+    manual.exclude::<Score>();
+    assert_eq!(manual, filter);
+}
+
+#[test]
+fn test_from_parts() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(1));
+    let _ = ecs.set(b, Score(2));
+    let next = ecs.next_id();
+    let parts: Vec<_> = ecs.drain().collect();
+    let mut rebuilt = Ecs::from_parts(parts, next);
+    assert_eq!(rebuilt.get::<Score>(a), Ok(Score(1)));
+    assert_eq!(rebuilt.count::<Score>(), 2);
+    let c = rebuilt.create_entity();
+    assert!(c != a && c != b);
+    let next = rebuilt.next_id();
+    let collected: Ecs = rebuilt.drain().collect();
+    assert_eq!(collected.next_id(), next);
+}
+
+#[test]
+#[should_panic]
+fn test_from_parts_rejects_stale_counter() {
+    let _ = Ecs::from_parts(vec![(Ecs::new().create_entity(), ComponentMap::new())], 0);
+}
+
+#[test]
+fn test_borrow_mut_pair() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(1));
+    let _ = ecs.set(b, Score(2));
+    {
+        let (sa, sb) = ecs.borrow_mut_pair::<Score>(a, b).unwrap();
+        std::mem::swap(sa, sb);
+    }
+    assert_eq!(ecs.get::<Score>(a), Ok(Score(2)));
+    assert_eq!(ecs.get::<Score>(b), Ok(Score(1)));
+    assert_eq!(ecs.borrow_mut_pair::<Score>(a, a).err(), Some(EcsError::AliasedEntity(a)));
+    let c = ecs.create_entity();
+    assert!(ecs.borrow_mut_pair::<Score>(a, c).is_err());
+    ecs.destroy_entity(c).unwrap();
+    assert_eq!(ecs.borrow_mut_pair::<Score>(c, a).err(), Some(EcsError::EntityNotFound(c)));
+}
+
+#[test]
+fn test_index_reuse() {
+    let mut ecs = Ecs::new();
+    let old = ecs.create_entity();
+    let _ = ecs.set(old, Score(1));
+    ecs.destroy_entity(old).unwrap();
+    let new = ecs.create_entity();
+    assert_eq!(new.index(), old.index());
+    assert_eq!(new.generation(), old.generation() + 1);
+    assert!(!ecs.exists(old));
+    assert_eq!(ecs.get::<Score>(old), Err(EcsError::EntityNotFound(old)));
+    assert_eq!(ecs.next_id(), 1);
+    assert_eq!(EcsError::EntityNotFound(new).to_string(), "entity 0v1 not found");
+}
+
+#[test]
+fn test_inspect() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let mut seen = None;
+    assert!(ecs.inspect(id, |s: &Score| seen = Some(s.0)).is_err());
+    let _ = ecs.set(id, Score(7));
+    assert_eq!(ecs.inspect(id, |s: &Score| seen = Some(s.0)), Ok(()));
+    assert_eq!(seen, Some(7));
+}
+
+#[test]
+fn test_component_type_names() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    let names = ecs.component_type_names(id).unwrap();
+    assert_eq!(names.len(), 2);
+    assert!(names[0].ends_with("Position"));
+    assert!(names[1].ends_with("Score"));
+}
+
+#[test]
+fn test_swap_components() {
+    struct Primary(u32);
+    struct Secondary(u32);
+    impl From<Secondary> for Primary {
+        fn from(s: Secondary) -> Self {
+            Primary(s.0)
+        }
+    }
+    impl From<Primary> for Secondary {
+        fn from(p: Primary) -> Self {
+            Secondary(p.0)
+        }
+    }
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Primary(1));
+    assert!(ecs.swap_components::<Primary, Secondary>(id).is_err());
+    assert_eq!(ecs.borrow::<Primary>(id).map(|p| p.0), Ok(1));
+    let _ = ecs.set(id, Secondary(2));
+    assert_eq!(ecs.swap_components::<Primary, Secondary>(id), Ok(()));
+    assert_eq!(ecs.borrow::<Primary>(id).map(|p| p.0), Ok(2));
+    assert_eq!(ecs.borrow::<Secondary>(id).map(|s| s.0), Ok(1));
+}
+
+#[test]
+fn test_on_destroy() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    let destroyed = Rc::new(RefCell::new(Vec::new()));
+    let mut ecs = Ecs::new();
+    {
+        let destroyed = destroyed.clone();
+        ecs.on_destroy(move |id| destroyed.borrow_mut().push(id));
+    }
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    ecs.destroy_entity(a).unwrap();
+    assert!(ecs.destroy_entity(a).is_err());
+    assert_eq!(*destroyed.borrow(), vec![a]);
+    let _ = ecs.drain().count();
+    assert_eq!(*destroyed.borrow(), vec![a, b]);
+}
+
+#[test]
+fn test_iter_entities() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(1));
+    let mut sizes: Vec<_> = ecs.iter_entities().map(|(id, map)| (id, map.len())).collect();
+    sizes.sort();
+    assert_eq!(sizes, vec![(a, 1), (b, 0)]);
+}
+
+#[test]
+fn test_has_exactly() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    assert_eq!(ecs.has_exactly(id, &component_filter!(Score, Position)), Ok(true));
+    assert_eq!(ecs.has_exactly(id, &component_filter!(Score)), Ok(false));
+    assert_eq!(ecs.has_exactly(id, &component_filter!(Score, Velocity)), Ok(false));
+}
+
+#[test]
+fn test_has_all_types() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    let types = [TypeId::of::<Score>(), TypeId::of::<Position>()];
+    assert_eq!(ecs.has_all_types(id, &types), Ok(true));
+    assert_eq!(ecs.has_all_types(id, &[TypeId::of::<Velocity>()]), Ok(false));
+    assert_eq!(ecs.has_all_types(id, &[]), Ok(true));
+}
+
+#[test]
+fn test_created_since() {
+    let mut ecs = Ecs::new();
+    let old = ecs.create_entity();
+    let _ = ecs.set(old, Score(0));
+    let checkpoint = ecs.generation();
+    assert_eq!(ecs.created_since(checkpoint).count(), 0);
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(old, Score(1));
+    let mut ids: Vec<_> = ecs.created_since(checkpoint).collect();
+    ids.sort();
+    assert_eq!(ids, vec![a, b]);
+    ecs.destroy_entity(a).unwrap();
+    assert_eq!(ecs.created_since(checkpoint).collect::<Vec<_>>(), vec![b]);
+}
+
+#[test]
+fn test_filter_type_ids() {
+    use std::any::TypeId;
+    let mut filter = component_filter!(Score);
+    filter.add_type_id(TypeId::of::<Position>());
+    assert_eq!(filter, component_filter!(Score, Position));
+    filter.remove_type_id(&TypeId::of::<Score>());
+    assert_eq!(filter, component_filter!(Position));
+}
+
+#[test]
+fn test_get_with() {
+    struct Name(String);
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Name("crate".to_string()));
+    assert_eq!(ecs.get_with(id, |n: &Name| n.0.len()), Ok(5));
+    assert!(ecs.get_with(id, |s: &Score| s.0).is_err());
+}
+
+#[test]
+fn test_ecs_eq() {
+    let mut expected = Ecs::new();
+    let id = expected.create_entity();
+    let _ = expected.set(id, Score(1));
+    let _ = expected.set(id, Position(Vector2f::new(0., 0.)));
+    let mut actual = Ecs::new();
+    let _ = actual.create_entity();
+    let _ = actual.set(id, Score(2));
+    let _ = actual.set(id, Position(Vector2f::new(5., 5.)));
+    // values of unregistered types aren't compared
+    assert!(actual == expected);
+    actual.register_eq::<Score>();
+    assert!(actual != expected);
+    let _ = actual.set(id, Score(1));
+    assert!(actual == expected);
+    let _ = actual.remove::<Position>(id);
+    assert!(actual != expected);
+}
+
+#[test]
+fn test_max_entities() {
+    let mut ecs = Ecs::with_max_entities(2);
+    let a = ecs.create_entity();
+    let _ = ecs.try_create_entity().unwrap();
+    assert_eq!(ecs.try_create_entity(), Err(EcsError::AtCapacity));
+    ecs.destroy_entity(a).unwrap();
+    assert!(ecs.try_create_entity().is_ok());
+    assert!(Ecs::new().try_create_entity().is_ok());
+}
+
+#[test]
+fn test_remove_if() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..4).map(|_| ecs.create_entity()).collect();
+    for (i, id) in ids.iter().enumerate() {
+        let _ = ecs.set(*id, Score(i as u32));
+    }
+    assert_eq!(ecs.remove_if(|s: &Score| s.0 < 2), 2);
+    assert_eq!(ecs.count::<Score>(), 2);
+    assert_eq!(ecs.has::<Score>(ids[0]), Ok(false));
+    assert_eq!(ecs.has::<Score>(ids[2]), Ok(true));
+}
+
+#[test]
+fn test_matches() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(3));
+    let high_score = |map: &ComponentMap| map.borrow::<Score>().is_ok_and(|s| s.0 > 2);
+    assert_eq!(ecs.matches(id, high_score), Ok(true));
+    let _ = ecs.set(id, Score(1));
+    assert_eq!(ecs.matches(id, high_score), Ok(false));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.matches(id, high_score), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_extend() {
+    let mut ecs = Ecs::new();
+    let maps = (0..3).map(|i| {
+        let mut map = ComponentMap::new();
+        map.set(Score(i));
+        map
+    });
+    let ids = ecs.extend(maps);
+    assert_eq!(ids.len(), 3);
+    assert_eq!(ecs.get::<Score>(ids[2]), Ok(Score(2)));
+    assert_eq!(ecs.with::<Score>().count(), 3);
+}
+
+#[test]
+fn test_new_ordered() {
+    let mut ecs = Ecs::new_ordered();
+    let ids: Vec<_> = (0..20).map(|_| ecs.create_entity()).collect();
+    for id in ids.iter().rev() {
+        let _ = ecs.set(*id, Score(0));
+    }
+    ecs.destroy_entity(ids[3]).unwrap();
+    let mut expected = ids.clone();
+    expected.remove(3);
+    let recycled = ecs.create_entity();
+    expected.push(recycled);
+    assert_eq!(ecs.iter().collect::<Vec<_>>(), expected);
+    let mut found = Vec::new();
+    ecs.collect_with(&component_filter!(Score), &mut found);
+    assert_eq!(found, &expected[..expected.len() - 1]);
+    let drained: Vec<_> = ecs.drain().map(|(id, _)| id).collect();
+    assert_eq!(drained, expected);
+}
+
+#[test]
+fn test_merge_with() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let add = |total: &mut Score, more: Score| total.0 += more.0;
+    assert_eq!(ecs.merge_with(id, Score(2), add), Ok(()));
+    assert_eq!(ecs.merge_with(id, Score(3), add), Ok(()));
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(5)));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.merge_with(id, Score(1), add), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_get_any() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(4));
+    let any = ecs.get_any(id, TypeId::of::<Score>()).unwrap();
+    assert_eq!(any.downcast_ref::<Score>(), Some(&Score(4)));
+    assert!(ecs.get_any(id, TypeId::of::<Position>()).is_err());
+}
+
+#[test]
+fn test_clear_components() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    assert_eq!(ecs.clear_components(id), Ok(()));
+    assert!(ecs.exists(id));
+    assert_eq!(ecs.count::<Score>(), 0);
+    assert_eq!(ecs.has::<Position>(id), Ok(false));
+}
+
+#[test]
+fn test_destroy_entities() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    ecs.destroy_entity(b).unwrap();
+    assert_eq!(ecs.destroy_entities(&[a, b]), vec![Ok(()), Err(EcsError::EntityNotFound(b))]);
+    assert!(!ecs.exists(a));
+}
+
+#[test]
+fn test_compute() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert!(ecs.compute(id, |s: Score| s).is_err());
+    let _ = ecs.set(id, Score(1));
+    assert_eq!(ecs.compute(id, |s: Score| Score(s.0 + 2)), Ok(Score(3)));
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(3)));
+}
+
+#[test]
+fn test_weak_entity() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let weak = WeakEntity::new(id);
+    assert_eq!(weak.upgrade(&ecs), Some(id));
+    ecs.destroy_entity(id).unwrap();
+    let _ = ecs.create_entity();
+    assert_eq!(weak.upgrade(&ecs), None);
+}
+
+#[test]
+fn test_generation_of() {
+    let mut ecs = Ecs::new();
+    let old = ecs.create_entity();
+    assert_eq!(ecs.generation_of(old), Some(0));
+    ecs.destroy_entity(old).unwrap();
+    let new = ecs.create_entity();
+    assert_eq!(ecs.generation_of(old), Some(1));
+    assert_eq!(ecs.generation_of(new), Some(1));
+    assert_eq!(Ecs::new().generation_of(new), None);
+}
+
+#[test]
+fn test_set_mut() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    ecs.set_mut(id, Score(1)).unwrap().0 += 1;
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(2)));
+    ecs.set_mut(id, Score(5)).unwrap().0 += 1;
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(6)));
+    assert_eq!(ecs.with::<Score>().collect::<Vec<_>>(), vec![id]);
+}
+
+#[test]
+fn test_compact() {
+    let mut ecs = Ecs::new_ordered();
+    let ids: Vec<_> = (0..100).map(|_| ecs.create_entity()).collect();
+    for id in &ids[1..] {
+        ecs.destroy_entity(*id).unwrap();
+    }
+    let _ = ecs.set(ids[0], Score(1));
+    ecs.compact();
+    assert_eq!(ecs.iter().collect::<Vec<_>>(), vec![ids[0]]);
+    assert_eq!(ecs.get::<Score>(ids[0]), Ok(Score(1)));
+}
+
+#[test]
+fn test_destroy_entity_counted() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    assert_eq!(ecs.destroy_entity_counted(id), Ok(2));
+    assert_eq!(ecs.destroy_entity_counted(id), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_iter_three() {
+    let mut ecs = Ecs::new();
+    let full = ecs.create_entity();
+    let partial = ecs.create_entity();
+    for id in &[full, partial] {
+        let _ = ecs.set(*id, Position(Vector2f::new(1., 1.)));
+        let _ = ecs.set(*id, Velocity(Vector2f::new(2., 2.)));
+    }
+    let _ = ecs.set(full, Score(3));
+    let two: Vec<_> = ecs.iter_two::<Position, Velocity>().map(|(id, _, _)| id).collect();
+    assert_eq!(two.len(), 2);
+    let three: Vec<_> = ecs.iter_three::<Position, Velocity, Score>()
+        .map(|(id, p, v, s)| (id, p.0.x + v.0.x, s.0))
+        .collect();
+    assert_eq!(three, vec![(full, 3., 3)]);
+}
+
+#[test]
+fn test_map_all() {
+    let mut ecs = Ecs::new();
+    for i in 0..3 {
+        let id = ecs.create_entity();
+        let _ = ecs.set(id, Score(i * 2));
+    }
+    let _ = ecs.create_entity();
+    assert_eq!(ecs.map_all(|s: &mut Score| s.0 /= 2), 3);
+    let mut scores: Vec<_> = ecs.iter_component::<Score>().map(|(_, s)| s.0).collect();
+    scores.sort();
+    assert_eq!(scores, vec![0, 1, 2]);
+}
+
+#[test]
+fn test_status() {
+    let mut ecs = Ecs::new();
+    let old = ecs.create_entity();
+    assert_eq!(ecs.status(old), EntityStatus::Alive);
+    ecs.destroy_entity(old).unwrap();
+    assert_eq!(ecs.status(old), EntityStatus::Dead { current_generation: 0 });
+    let new = ecs.create_entity();
+    assert_eq!(ecs.status(old), EntityStatus::Dead { current_generation: 1 });
+    assert_eq!(Ecs::new().status(new), EntityStatus::NeverExisted);
+}
+
+#[test]
+fn test_collect_with_excluding() {
+    let mut ecs = Ecs::new();
+    let alive = ecs.create_entity();
+    let frozen = ecs.create_entity();
+    let _ = ecs.set_many(&[alive, frozen], Score(0));
+    let _ = ecs.set(frozen, Velocity(Vector2f::new(0., 0.)));
+    let mut ids = vec![frozen];
+    ecs.collect_with_excluding(&component_filter!(Score), &component_filter!(Velocity), &mut ids);
+    assert_eq!(ids, vec![alive]);
+}
+
+#[test]
+fn test_query() {
+    let mut ecs = Ecs::new();
+    let moving = ecs.create_entity();
+    let still = ecs.create_entity();
+    let _ = ecs.set_many(&[moving, still], Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(moving, Velocity(Vector2f::new(1., 0.)));
+    let _ = ecs.set(still, Score(9));
+    let query = ecs.query().with::<Position>().without::<Velocity>();
+    assert_eq!(query.iter().collect::<Vec<_>>(), vec![still]);
+    assert_eq!(query.iter_component::<Score>().map(|(_, s)| s.0).collect::<Vec<_>>(), vec![9]);
+    assert_eq!(query.matches(moving), Ok(false));
+    assert_eq!(ecs.query().count(), 2);
+    assert_eq!(ecs.query().with::<Velocity>().count(), 1);
+}
+
+#[test]
+fn test_component_age() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(0));
+    let score = TypeId::of::<Score>();
+    assert_eq!(ecs.component_age(id, score), Err(EcsError::ComponentNotFound(score)));
+    ecs.enable_component_ticks();
+    ecs.advance_tick();
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    assert_eq!(ecs.advance_tick(), 2);
+    assert_eq!(ecs.component_age(id, score), Ok(2));
+    assert_eq!(ecs.component_age(id, TypeId::of::<Position>()), Ok(1));
+    let _ = ecs.set(id, Score(1));
+    assert_eq!(ecs.component_age(id, score), Ok(0));
+    let _ = ecs.remove::<Score>(id);
+    assert_eq!(ecs.component_age(id, score), Err(EcsError::ComponentNotFound(score)));
+}
+
+#[test]
+fn test_into_iter() {
+    let mut ecs = Ecs::new_ordered();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(b, Score(2));
+    let entities: Vec<_> = ecs.into_iter().map(|(id, map)| (id, map.len())).collect();
+    assert_eq!(entities, vec![(a, 0), (b, 1)]);
+}
+
+#[test]
+fn test_filter_matches_map() {
+    let mut map = ComponentMap::new();
+    map.set(Score(1));
+    assert!(component_filter!(Score).matches(&map));
+    assert!(!component_filter!(Score, Position).matches(&map));
+    assert!(!component_filter_excluding!(; Score).matches(&map));
+    let prefab = Prefab::new().with(Score(0));
+    assert!(component_filter!(Score).matches(prefab.components()));
+}
+
+#[test]
+fn test_has_all_many() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let gone = ecs.create_entity();
+    ecs.destroy_entity(gone).unwrap();
+    let _ = ecs.set(b, Score(1));
+    assert_eq!(ecs.has_all_many(&[b, a, gone], &component_filter!(Score)),
+               vec![Ok(true), Ok(false), Err(EcsError::EntityNotFound(gone))]);
+}
+
+#[test]
+fn test_components_macro() {
+    let mut ecs = Ecs::new();
+    components!(ecs; Score, Position);
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    let mut copy = ecs.clone();
+    assert_eq!(copy.get::<Score>(id), Ok(Score(1)));
+    components!(&mut copy, eq; Score);
+    let _ = copy.set(id, Score(2));
+    assert!(copy != ecs);
+}
+
+#[test]
+fn test_checked_accessors() {
+    let mut map = ComponentMap::new();
+    assert_eq!(map.try_set(Score(1)), Ok(None));
+    assert_eq!(map.try_get::<Score>(), Ok(Score(1)));
+    map.try_borrow_mut::<Score>().unwrap().0 = 2;
+    assert_eq!(map.try_borrow::<Score>(), Ok(&Score(2)));
+    assert_eq!(map.try_remove::<Score>(), Ok(Some(Score(2))));
+    assert!(map.try_borrow::<Score>().is_err());
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(3));
+    let checked: Vec<_> = ecs.iter_component_checked::<Score>().collect();
+    assert_eq!(checked, vec![Ok((id, &Score(3)))]);
+}
+
+#[test]
+fn test_get_cow() {
+    use std::borrow::Cow;
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    let mut score = ecs.get_cow::<Score>(id).unwrap();
+    assert!(match score {
+        Cow::Borrowed(_) => true,
+        Cow::Owned(_) => false,
+    });
+    score.to_mut().0 = 5;
+    assert_eq!(*score, Score(5));
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(1)));
+}
+
+#[test]
+fn test_component_type_counts() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..3).map(|_| ecs.create_entity()).collect();
+    let _ = ecs.set_many(&ids, Score(0));
+    let _ = ecs.set(ids[0], Position(Vector2f::new(0., 0.)));
+    let counts = ecs.component_type_counts();
+    assert_eq!(counts.len(), 2);
+    assert_eq!(counts[&TypeId::of::<Score>()], 3);
+    assert_eq!(counts[&TypeId::of::<Position>()], 1);
+}
+
+#[test]
+fn test_swap_entities() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(1));
+    let _ = ecs.set(b, Position(Vector2f::new(0., 0.)));
+    assert_eq!(ecs.swap_entities(a, b), Ok(()));
+    assert_eq!(ecs.get::<Score>(b), Ok(Score(1)));
+    assert_eq!(ecs.has::<Position>(a), Ok(true));
+    assert_eq!(ecs.with::<Score>().collect::<Vec<_>>(), vec![b]);
+    assert_eq!(ecs.swap_entities(a, a), Ok(()));
+    ecs.destroy_entity(b).unwrap();
+    assert_eq!(ecs.swap_entities(a, b), Err(EcsError::EntityNotFound(b)));
+}
+
+#[test]
+fn test_validate() {
+    let mut ecs = Ecs::new_ordered();
+    ecs.register_presence_bit::<Score>();
+    let parent = ecs.create_entity();
+    let child = ecs.create_entity();
+    let _ = ecs.set(child, Score(1));
+    ecs.set_parent(child, parent).unwrap();
+    ecs.set_enabled(parent, false).unwrap();
+    assert_eq!(ecs.validate(), Ok(()));
+    ecs.destroy_entity(parent).unwrap();
+    let _ = ecs.remove::<Score>(child);
+    assert_eq!(ecs.validate(), Ok(()));
+    let parts: Vec<_> = ecs.drain().collect();
+    assert_eq!(Ecs::from_parts(parts, 2).validate(), Ok(()));
+}
+
+#[test]
+fn test_append_with() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(0));
+    let _ = ecs.set(b, Position(Vector2f::new(0., 0.)));
+    let mut ids = Vec::new();
+    ecs.append_with(&component_filter!(Score), &mut ids);
+    ecs.append_with(&component_filter!(Position), &mut ids);
+    assert_eq!(ids, vec![a, b]);
+}
+
+#[test]
+fn test_userdata() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.userdata(id), Ok(0));
+    ecs.set_userdata(id, 42).unwrap();
+    assert_eq!(ecs.userdata(id), Ok(42));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.userdata(id), Err(EcsError::EntityNotFound(id)));
+    assert_eq!(ecs.set_userdata(id, 1), Err(EcsError::EntityNotFound(id)));
+    let recycled = ecs.create_entity();
+    assert_eq!(ecs.userdata(recycled), Ok(0));
+}
+
+#[test]
+fn test_borrow_or_insert() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.borrow_or_insert::<Score>(id), Ok(&Score(0)));
+    ecs.borrow_mut::<Score>(id).unwrap().0 = 3;
+    assert_eq!(ecs.borrow_or_insert::<Score>(id), Ok(&Score(3)));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.borrow_or_insert::<Score>(id), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_clone_sendable() {
+    let mut ecs = Ecs::new();
+    ecs.register_send::<Score>();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(7));
+    let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+    let snapshot = ecs.clone_sendable();
+    assert_eq!(snapshot.skipped(), &[std::any::TypeId::of::<Position>()]);
+    let score = std::thread::spawn(move || snapshot.borrow::<Score>(id).cloned()).join().unwrap();
+    assert_eq!(score, Some(Score(7)));
+}
+
+#[test]
+fn test_destroy_with() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let c = ecs.create_entity();
+    let _ = ecs.set(a, Score(0));
+    let _ = ecs.set(b, Score(1));
+    let _ = ecs.set(c, Position(Vector2f::new(0., 0.)));
+    assert_eq!(ecs.destroy_with(&component_filter!(Score)), 2);
+    assert!(!ecs.exists(a) && !ecs.exists(b) && ecs.exists(c));
+    assert_eq!(ecs.destroy_with(&component_filter!(Score)), 0);
+}
+
+#[test]
+fn test_cleanup_hook() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    struct Handle(Rc<RefCell<Vec<EntityId>>>);
+    impl DroppableComponent for Handle {
+        fn on_remove(&mut self, id: EntityId) {
+            self.0.borrow_mut().push(id);
+        }
+    }
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut ecs = Ecs::new();
+    ecs.register_cleanup::<Handle>();
+    let ids: Vec<_> = (0..3).map(|_| ecs.create_entity()).collect();
+    for id in &ids {
+        let _ = ecs.set(*id, Handle(log.clone()));
+    }
+    let _ = ecs.remove::<Handle>(ids[0]);
+    ecs.clear_components(ids[1]).unwrap();
+    ecs.destroy_entity(ids[2]).unwrap();
+    let _ = ecs.remove::<Handle>(ids[0]);
+    assert_eq!(*log.borrow(), ids);
+}
+
+#[test]
+fn test_collect_with_types() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(0));
+    let _ = ecs.set(a, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(b, Score(1));
+    let mut ids = vec![b];
+    ecs.collect_with_types(&[TypeId::of::<Score>(), TypeId::of::<Position>()], &mut ids);
+    assert_eq!(ids, vec![a]);
+    ecs.collect_with_types(&[], &mut ids);
+    assert_eq!(ids.len(), 2);
+}
+
+#[test]
+fn test_is_bare() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.is_bare(id), Ok(true));
+    let _ = ecs.set(id, Score(0));
+    assert_eq!(ecs.is_bare(id), Ok(false));
+    let _ = ecs.remove::<Score>(id);
+    assert_eq!(ecs.is_bare(id), Ok(true));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.is_bare(id), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_entity_ids() {
+    let mut ecs = Ecs::new_ordered();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let c = ecs.create_entity();
+    ecs.destroy_entity(b).unwrap();
+    assert_eq!(ecs.entity_ids(), Some(&[a, c][..]));
+    assert_eq!(Ecs::new().entity_ids(), None);
+}
+
+#[test]
+fn test_apply_prefab() {
+    let base = Prefab::new().with(Score(1)).with(Position(Vector2f::new(1., 1.)));
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(5));
+    ecs.apply_prefab(id, &base, false).unwrap();
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(5)));
+    assert!(ecs.has::<Position>(id).unwrap());
+    assert!(ecs.component_type_names(id).unwrap()[0].ends_with("Position"));
+    ecs.apply_prefab(id, &base, true).unwrap();
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(1)));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.apply_prefab(id, &base, true), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_filter_debug_names() {
+    let filter = component_filter_excluding!(Score, Position; Velocity);
+    assert_eq!(format!("{:?}", filter),
+               "ComponentFilter { mod::Position, mod::Score, !mod::Velocity }");
+    let mut built = ComponentFilter::new();
+    built.add::<Score>();
+    assert_eq!(format!("{:?}", built), "ComponentFilter { mod::Score }");
+    assert_eq!(built, component_filter!(Score));
+    assert_eq!(format!("{:?}", ComponentFilter::new()), "ComponentFilter {}");
+}
+
+#[test]
+fn test_create_with_rollback() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_with(|e| -> Result<(), ()> {
+            e.set(Score(1)).set(Position(Vector2f::new(0., 0.)));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(1)));
+    let failed = ecs.create_with(|e| {
+        e.set(Score(2));
+        if e.ecs().count::<Score>() > 1 { Err("duplicate score") } else { Ok(()) }
+    });
+    assert_eq!(failed, Err("duplicate score"));
+    assert_eq!(ecs.count::<Score>(), 1);
+    assert_eq!(ecs.iter().count(), 1);
+}
+
+#[test]
+fn test_borrow_mut_or() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    ecs.borrow_mut_or(id, Score(10)).unwrap().0 += 1;
+    ecs.borrow_mut_or(id, Score(10)).unwrap().0 += 1;
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(12)));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.borrow_mut_or(id, Score(0)).map(|s| s.0),
+               Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_collect_where() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..4).map(|_| ecs.create_entity()).collect();
+    for (i, id) in ids.iter().enumerate() {
+        let _ = ecs.set(*id, Score(i as u32));
+    }
+    let mut found = vec![ids[0]];
+    ecs.collect_where(|_, map| map.borrow::<Score>().is_ok_and(|s| s.0 >= 2), &mut found);
+    found.sort();
+    assert_eq!(found, vec![ids[2], ids[3]]);
+}
+
+#[test]
+fn test_memory_estimate() {
+    let mut ecs = Ecs::new();
+    let empty = ecs.memory_estimate();
+    let ids: Vec<_> = (0..100).map(|_| ecs.create_entity()).collect();
+    let bare = ecs.memory_estimate();
+    assert!(bare > empty);
+    for id in &ids {
+        let _ = ecs.set(*id, Score(0));
+    }
+    assert!(ecs.memory_estimate() > bare);
+}
+
+#[test]
+fn test_iter_hierarchy() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..5).map(|_| ecs.create_entity()).collect();
+    ecs.set_parent(ids[1], ids[0]).unwrap();
+    ecs.set_parent(ids[2], ids[1]).unwrap();
+    ecs.set_parent(ids[3], ids[0]).unwrap();
+    ecs.set_parent(ids[4], ids[3]).unwrap();
+    let walk: Vec<_> = ecs.iter_hierarchy(ids[0]).collect();
+    assert_eq!(walk, vec![(ids[1], 1), (ids[2], 2), (ids[3], 1), (ids[4], 2)]);
+    assert_eq!(ecs.iter_hierarchy(ids[2]).count(), 0);
+}
+
+#[test]
+fn test_set_logged() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.set_logged(id, Score(1)),
+               Ok(ChangeRecord { old: None, new: Score(1) }));
+    assert_eq!(ecs.set_logged(id, Score(2)),
+               Ok(ChangeRecord { old: Some(Score(1)), new: Score(2) }));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.set_logged(id, Score(3)), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_get_or_create_at() {
+    let mut ecs = Ecs::new();
+    let old = ecs.create_entity();
+    ecs.destroy_entity(old).unwrap();
+    let loaded = ecs.create_entity();
+    ecs.destroy_entity(loaded).unwrap();
+    assert!(ecs.get_or_create_at(loaded).unwrap().is_empty());
+    let _ = ecs.set(loaded, Score(1));
+    assert_eq!(ecs.get_or_create_at(loaded).unwrap().get::<Score>(), Ok(Score(1)));
+    assert!(ecs.get_or_create_at(old).is_err());
+    let mut saved = Ecs::new();
+    let far = (0..11).map(|_| saved.create_entity()).last().unwrap();
+    assert_eq!(ecs.get_or_create_at(far).unwrap().len(), 0);
+    let fresh = ecs.create_entity();
+    assert_eq!(fresh.index(), 11);
+    assert_eq!(ecs.validate(), Ok(()));
+}
+
+#[test]
+fn test_archetype_census() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    for i in 0..5 {
+        let id = ecs.create_entity();
+        let _ = ecs.set(id, Score(i));
+        if i < 2 {
+            let _ = ecs.set(id, Position(Vector2f::new(0., 0.)));
+        }
+    }
+    ecs.create_entity();
+    let mut pair = vec![TypeId::of::<Score>(), TypeId::of::<Position>()];
+    pair.sort();
+    assert_eq!(ecs.archetype_census(),
+               vec![(vec![TypeId::of::<Score>()], 3), (pair, 2), (vec![], 1)]);
+}
+
+#[test]
+fn test_compare_and_set() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    assert_eq!(ecs.compare_and_set(id, &Score(0), Score(1)),
+               Err(EcsError::ComponentNotFound(std::any::TypeId::of::<Score>())));
+    let _ = ecs.set(id, Score(0));
+    assert_eq!(ecs.compare_and_set(id, &Score(0), Score(1)), Ok(true));
+    assert_eq!(ecs.compare_and_set(id, &Score(0), Score(2)), Ok(false));
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(1)));
+}
+
+#[test]
+fn test_iter_with_maps() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(3));
+    let _ = ecs.set(a, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(b, Position(Vector2f::new(0., 0.)));
+    let filter = component_filter!(Position);
+    let mut scores: Vec<_> = ecs.iter_with_maps(&filter)
+        .map(|(id, map)| (id, map.get::<Score>().ok().map(|s| s.0)))
+        .collect();
+    scores.sort();
+    assert_eq!(scores, vec![(a, Some(3)), (b, None)]);
+}
+
+#[test]
+fn test_copy_component() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(4));
+    ecs.copy_component::<Score>(a, b).unwrap();
+    assert_eq!(ecs.get::<Score>(a), Ok(Score(4)));
+    assert_eq!(ecs.get::<Score>(b), Ok(Score(4)));
+    assert_eq!(ecs.copy_component::<Position>(a, b),
+               Err(EcsError::ComponentNotFound(std::any::TypeId::of::<Position>())));
+    ecs.destroy_entity(b).unwrap();
+    assert_eq!(ecs.copy_component::<Score>(a, b), Err(EcsError::EntityNotFound(b)));
+}
+
+#[test]
+fn test_with_entity() {
+    let mut ecs = Ecs::new();
+    ecs.register_presence_bit::<Position>();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(1));
+    assert_eq!(ecs.with_entity(id, |map| map.len()), Ok(1));
+    ecs.with_entity_mut(id, |map| {
+            map.borrow_mut::<Score>().unwrap().0 += 1;
+            map.set(Position(Vector2f::new(0., 0.)));
+        })
+        .unwrap();
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(2)));
+    assert_eq!(ecs.count::<Position>(), 1);
+    assert!(ecs.has_all(id, &component_filter!(Position)).unwrap());
+    let names = ecs.component_type_names(id).unwrap();
+    assert!(names.iter().any(|name| name.ends_with("Position")));
+    ecs.with_entity_mut(id, |map| map.remove::<Score>()).unwrap();
+    assert_eq!(ecs.count::<Score>(), 0);
+    assert_eq!(ecs.validate(), Ok(()));
+    // only removed components need to be cloneable while checkpoints are in use
+    ecs.register_clone::<Position>();
+    let _ = ecs.set(id, Score(3));
+    let checkpoint = ecs.checkpoint();
+    ecs.with_entity_mut(id, |map| map.borrow_mut::<Score>().unwrap().0 += 1).unwrap();
+    ecs.with_entity_mut(id, |map| map.remove::<Position>()).unwrap();
+    ecs.rollback_to(&checkpoint).unwrap();
+    assert!(ecs.has::<Position>(id).unwrap());
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(4)));
+    ecs.clear_checkpoints();
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.with_entity(id, |_| ()), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_iter_component_where() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..4).map(|_| ecs.create_entity()).collect();
+    for (i, id) in ids.iter().enumerate().skip(1) {
+        let _ = ecs.set(*id, Score(i as u32 % 2));
+    }
+    let mut zero: Vec<_> = ecs.iter_component_where(|s: &Score| s.0 == 0).collect();
+    zero.sort();
+    assert_eq!(zero, vec![ids[2]]);
+}
+
+#[test]
+fn test_take_resource() {
+    let mut ecs = Ecs::new();
+    assert_eq!(ecs.take_resource::<Vec<u32>>(), None);
+    ecs.insert_resource(vec![1u32]);
+    ecs.resource_mut::<Vec<u32>>().unwrap().push(2);
+    assert_eq!(ecs.resource::<Vec<u32>>(), Some(&vec![1, 2]));
+    assert_eq!(ecs.take_resource::<Vec<u32>>(), Some(vec![1, 2]));
+    assert_eq!(ecs.resource::<Vec<u32>>(), None);
+}
+
+#[test]
+fn test_changed_since() {
+    let mut ecs = Ecs::new();
+    ecs.enable_component_ticks();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(0));
+    let last_run = ecs.advance_tick();
+    assert_eq!(ecs.changed_since::<Score>(id, last_run), Ok(false));
+    ecs.advance_tick();
+    let _ = ecs.set(id, Score(1));
+    assert_eq!(ecs.changed_since::<Score>(id, last_run), Ok(true));
+    assert_eq!(ecs.changed_since::<Position>(id, last_run),
+               Err(EcsError::ComponentNotFound(std::any::TypeId::of::<Position>())));
+}
+
+#[test]
+fn test_spawn_bundle() {
+    let mut ecs = Ecs::new();
+    let id = ecs.spawn((Score(2), Position(Vector2f::new(1., 2.))));
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(2)));
+    assert_eq!(ecs.get::<Position>(id), Ok(Position(Vector2f::new(1., 2.))));
+    let names = ecs.component_type_names(id).unwrap();
+    assert!(names[0].ends_with("Position"));
+    assert!(names[1].ends_with("Score"));
+    let single = ecs.spawn((Score(3),));
+    assert_eq!(ecs.count::<Score>(), 2);
+    assert_eq!(ecs.is_bare(single), Ok(false));
+}
+
+#[test]
+fn test_insert_remove_bundle() {
+    let mut ecs = Ecs::new();
+    let id = ecs.spawn((Score(1),));
+    ecs.insert(id, (Score(2), Position(Vector2f::new(0., 0.)))).unwrap();
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(2)));
+    assert_eq!(ecs.count::<Position>(), 1);
+    assert!(ecs.component_type_names(id).unwrap()[0].ends_with("Position"));
+    ecs.remove_bundle::<(Position, Velocity)>(id).unwrap();
+    assert_eq!(ecs.count::<Position>(), 0);
+    assert_eq!(ecs.get::<Score>(id), Ok(Score(2)));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.insert(id, (Score(0),)), Err(EcsError::EntityNotFound(id)));
+    assert_eq!(ecs.remove_bundle::<(Score,)>(id), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_internal_query_cache() {
+    let mut ecs = Ecs::new();
+    ecs.enable_query_cache();
+    let a = ecs.create_entity();
+    let _ = ecs.set(a, Score(0));
+    let mut ids = Vec::new();
+    ecs.collect_with(&component_filter!(Score), &mut ids);
+    assert_eq!(ids, vec![a]);
+    ecs.borrow_mut::<Score>(a).unwrap().0 = 5;
+    ecs.collect_with(&component_filter!(Score), &mut ids);
+    assert_eq!(ids, vec![a]);
+    let b = ecs.create_entity();
+    let _ = ecs.set(b, Score(1));
+    ecs.collect_with(&component_filter!(Score), &mut ids);
+    ids.sort();
+    assert_eq!(ids, vec![a, b]);
+    ecs.collect_with(&component_filter_excluding!(Score; Position), &mut ids);
+    assert_eq!(ids.len(), 2);
+    let _ = ecs.set(a, Position(Vector2f::new(0., 0.)));
+    ecs.collect_with(&component_filter_excluding!(Score; Position), &mut ids);
+    assert_eq!(ids, vec![b]);
+}
+
+#[test]
+fn test_map_filtered() {
+    let mut ecs = Ecs::new();
+    let moving = ecs.spawn((Position(Vector2f::new(0., 0.)), Velocity(Vector2f::new(1., 2.))));
+    let still = ecs.spawn((Position(Vector2f::new(5., 5.)),));
+    let visited = ecs.map_filtered(&component_filter!(Position, Velocity),
+                                   |pos: &mut Position, map| {
+                                       *pos = update_position(pos, map.borrow().unwrap());
+                                   });
+    assert_eq!(visited, 1);
+    assert_eq!(ecs.get::<Position>(moving), Ok(Position(Vector2f::new(1., 2.))));
+    assert_eq!(ecs.get::<Position>(still), Ok(Position(Vector2f::new(5., 5.))));
+}
+
+#[test]
+fn test_signature() {
+    let mut ecs = Ecs::new();
+    let a = ecs.spawn((Score(0), Position(Vector2f::new(0., 0.))));
+    let b = ecs.spawn((Position(Vector2f::new(1., 1.)), Score(1)));
+    let c = ecs.spawn((Score(2),));
+    assert_eq!(ecs.signature(a), ecs.signature(b));
+    assert!(ecs.signature(a) != ecs.signature(c));
+    let _ = ecs.remove::<Position>(a);
+    assert_eq!(ecs.signature(a), ecs.signature(c));
+    ecs.with_entity_mut(a, |map| map.set(Position(Vector2f::new(0., 0.)))).unwrap();
+    assert_eq!(ecs.signature(a), ecs.signature(b));
+    ecs.destroy_entity(c).unwrap();
+    assert_eq!(ecs.signature(c), Err(EcsError::EntityNotFound(c)));
+}
+
+#[test]
+fn test_entity_eq() {
+    let mut ecs = Ecs::new();
+    ecs.register_eq::<Score>();
+    let a = ecs.spawn((Score(1), Position(Vector2f::new(0., 0.))));
+    let mut other = Ecs::new();
+    other.create_entity();
+    let b = other.spawn((Position(Vector2f::new(9., 9.)), Score(1)));
+    assert!(ecs.entity_eq(a, &other, b));
+    let _ = other.set(b, Score(2));
+    assert!(!ecs.entity_eq(a, &other, b));
+    let _ = other.set(b, Score(1));
+    let _ = other.remove::<Position>(b);
+    assert!(!ecs.entity_eq(a, &other, b));
+    assert!(!ecs.entity_eq(b, &other, b));
+}
+
+#[test]
+fn test_drain_with() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    let destroyed = Rc::new(RefCell::new(Vec::new()));
+    let mut ecs = Ecs::new();
+    let log = destroyed.clone();
+    ecs.on_destroy(move |id| log.borrow_mut().push(id));
+    let expired = ecs.spawn((Score(1), Position(Vector2f::new(0., 0.))));
+    let kept = ecs.spawn((Position(Vector2f::new(0., 0.)),));
+    let drained = ecs.drain_with(&component_filter!(Score));
+    assert_eq!(drained.len(), 1);
+    assert_eq!(drained[0].0, expired);
+    assert_eq!(drained[0].1.get::<Score>(), Ok(Score(1)));
+    assert_eq!(*destroyed.borrow(), vec![expired]);
+    assert!(!ecs.exists(expired) && ecs.exists(kept));
+    assert_eq!(ecs.validate(), Ok(()));
+}
+
+#[test]
+fn test_for_each_pair() {
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..4).map(|i| ecs.spawn((Score(i),))).collect();
+    ecs.spawn((Position(Vector2f::new(0., 0.)),));
+    let mut pairs = HashSet::new();
+    ecs.for_each_pair(&component_filter!(Score), |a, b, ecs| {
+        assert!(ecs.has::<Score>(a).unwrap() && a != b);
+        assert!(pairs.insert((a.min(b), a.max(b))));
+    });
+    assert_eq!(pairs.len(), 6);
+    assert!(pairs.contains(&(ids[0], ids[3])));
+}
+
+#[test]
+fn test_which_of() {
+    let mut ecs = Ecs::new();
+    let id = ecs.spawn((Score(0), Position(Vector2f::new(0., 0.))));
+    let present = ecs.which_of(id, &component_filter!(Score, Velocity)).unwrap();
+    assert_eq!(present, component_filter!(Score));
+    assert_eq!(format!("{:?}", present), "ComponentFilter { mod::Score }");
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.which_of(id, &component_filter!(Score)), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_total_components() {
+    let mut ecs = Ecs::new();
+    assert_eq!(ecs.total_components(), 0);
+    let a = ecs.spawn((Score(0), Position(Vector2f::new(0., 0.))));
+    ecs.spawn((Score(1),));
+    ecs.create_entity();
+    assert_eq!(ecs.total_components(), 3);
+    ecs.destroy_entity(a).unwrap();
+    assert_eq!(ecs.total_components(), 1);
+}
+
+#[test]
+fn test_dedup_ids() {
+    let mut ecs = Ecs::new();
+    let both = ecs.spawn((Score(0), Position(Vector2f::new(0., 0.))));
+    let score = ecs.spawn((Score(1),));
+    let pos = ecs.spawn((Position(Vector2f::new(1., 1.)),));
+    let mut ids = Vec::new();
+    ecs.collect_with(&component_filter!(Score), &mut ids);
+    assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+    ecs.append_with(&component_filter!(Position), &mut ids);
+    assert_eq!(ids.len(), 4);
+    Ecs::dedup_ids(&mut ids);
+    assert_eq!(ids, vec![both, score, pos]);
+}
+
+#[test]
+fn test_reserve_components() {
+    let mut ecs = Ecs::new();
+    let id = ecs.create_entity();
+    let before = ecs.memory_estimate();
+    ecs.reserve_components(id, 32).unwrap();
+    assert!(ecs.memory_estimate() > before);
+    assert_eq!(ecs.is_bare(id), Ok(true));
+    ecs.destroy_entity(id).unwrap();
+    assert_eq!(ecs.reserve_components(id, 1), Err(EcsError::EntityNotFound(id)));
+}
+
+#[test]
+fn test_shared_types() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    let _ = ecs.set(a, Score(0));
+    let _ = ecs.set(a, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.set(b, Score(1));
+    let _ = ecs.set(b, Velocity(Vector2f::new(0., 0.)));
+    let shared = ecs.shared_types(a, b).unwrap();
+    assert_eq!(shared, component_filter!(Score));
+    assert_eq!(format!("{:?}", shared), "ComponentFilter { mod::Score }");
+    ecs.destroy_entity(b).unwrap();
+    assert_eq!(ecs.shared_types(a, b), Err(EcsError::EntityNotFound(b)));
+}
+
+#[test]
+fn test_event_log() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let early = ecs.create_entity();
+    ecs.enable_event_log();
+    let id = ecs.create_entity();
+    let _ = ecs.set(id, Score(0));
+    let _ = ecs.set_mut(id, Position(Vector2f::new(0., 0.)));
+    let _ = ecs.remove::<Score>(id);
+    let _ = ecs.remove::<Score>(id);
+    ecs.destroy_entity(id).unwrap();
+    let score = TypeId::of::<Score>();
+    assert_eq!(ecs.drain_events(),
+               vec![EcsEvent::EntityCreated(id),
+                    EcsEvent::ComponentSet(id, score),
+                    EcsEvent::ComponentSet(id, TypeId::of::<Position>()),
+                    EcsEvent::ComponentRemoved(id, score),
+                    EcsEvent::EntityDestroyed(id)]);
+    assert_eq!(ecs.drain_events(), vec![]);
+    let _ = ecs.drain().count();
+    assert_eq!(ecs.drain_events(), vec![EcsEvent::EntityDestroyed(early)]);
+}
+
+#[test]
+fn test_rollback_to() {
+    let mut ecs = Ecs::new();
+    ecs.register_clone::<Score>();
+    let before = ecs.create_entity();
+    let _ = ecs.set(before, Score(1));
+    let doomed = ecs.create_entity();
+    let _ = ecs.set(doomed, Score(2));
+    let checkpoint = ecs.checkpoint();
+    let _ = ecs.set(before, Score(10));
+    let _ = ecs.set_mut(before, Position(Vector2f::new(1., 2.)));
+    ecs.destroy_entity(doomed).unwrap();
+    let added = ecs.create_entity();
+    let _ = ecs.set(added, Score(3));
+    let later = ecs.checkpoint();
+    let _ = ecs.remove::<Score>(before);
+    ecs.rollback_to(&checkpoint).unwrap();
+    assert_eq!(ecs.get::<Score>(before), Ok(Score(1)));
+    assert!(!ecs.has::<Position>(before).unwrap());
+    assert_eq!(ecs.get::<Score>(doomed), Ok(Score(2)));
+    assert!(!ecs.exists(added));
+    let mut ids = Vec::new();
+    ecs.collect_with(&component_filter!(Score), &mut ids);
+    ids.sort();
+    assert_eq!(ids, vec![before, doomed]);
+    assert_eq!(ecs.rollback_to(&later), Err(EcsError::InvalidCheckpoint));
+    // the checkpoint itself stays valid
+    let _ = ecs.set(doomed, Score(20));
+    ecs.rollback_to(&checkpoint).unwrap();
+    assert_eq!(ecs.get::<Score>(doomed), Ok(Score(2)));
+    ecs.clear_checkpoints();
+    assert_eq!(ecs.rollback_to(&checkpoint), Err(EcsError::InvalidCheckpoint));
+}
+
+#[test]
+fn test_borrow_mut_across() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let attacker = ecs.create_entity();
+    let defender = ecs.create_entity();
+    let _ = ecs.set(attacker, Score(5));
+    let _ = ecs.set(defender, Position(Vector2f::new(0., 0.)));
+    {
+        let (score, pos) = ecs.borrow_mut_across::<Score, Position>(attacker, defender).unwrap();
+        score.0 -= 1;
+        pos.0.x = score.0 as f32;
+    }
+    assert_eq!(ecs.get::<Score>(attacker), Ok(Score(4)));
+    assert_eq!(ecs.get::<Position>(defender), Ok(Position(Vector2f::new(4., 0.))));
+    let _ = ecs.set(attacker, Position(Vector2f::new(1., 1.)));
+    assert!(ecs.borrow_mut_across::<Score, Position>(attacker, attacker).is_ok());
+    assert_eq!(ecs.borrow_mut_across::<Score, Score>(attacker, attacker).err(),
+               Some(EcsError::Aliased(TypeId::of::<Score>())));
+    assert_eq!(ecs.borrow_mut_across::<Score, Score>(attacker, defender).err(),
+               Some(EcsError::ComponentNotFound(TypeId::of::<Score>())));
+    ecs.destroy_entity(defender).unwrap();
+    assert_eq!(ecs.borrow_mut_across::<Score, Position>(attacker, defender).err(),
+               Some(EcsError::EntityNotFound(defender)));
+}
+
+#[test]
+fn test_chunks() {
+    let mut ecs = Ecs::new();
+    let mut ids: Vec<_> = (0..10).map(|_| ecs.create_entity()).collect();
+    ecs.destroy_entity(ids.remove(3)).unwrap();
+    let chunks = ecs.chunks(4);
+    assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 2, 2, 2]);
+    assert_eq!(chunks.concat(), ids);
+    assert_eq!(ecs.clone().chunks(4), chunks);
+    let sparse = ecs.chunks(12);
+    assert_eq!(sparse.len(), 12);
+    assert!(sparse[9..].iter().all(Vec::is_empty));
+}
+
+#[test]
+fn test_relations() {
+    struct Targets;
+    struct Owns;
+    let mut ecs = Ecs::new();
+    let archer = ecs.create_entity();
+    let goblin = ecs.create_entity();
+    let orc = ecs.create_entity();
+    ecs.relate::<Targets>(archer, goblin).unwrap();
+    ecs.relate::<Targets>(archer, orc).unwrap();
+    ecs.relate::<Targets>(orc, goblin).unwrap();
+    ecs.relate::<Owns>(goblin, archer).unwrap();
+    assert_eq!(ecs.related::<Targets>(archer), vec![goblin, orc]);
+    assert_eq!(ecs.related::<Owns>(archer), vec![]);
+    assert_eq!(ecs.relations_to::<Targets>(goblin), vec![archer, orc]);
+    assert!(ecs.unrelate::<Targets>(archer, orc));
+    assert!(!ecs.unrelate::<Targets>(archer, orc));
+    assert_eq!(ecs.related::<Targets>(archer), vec![goblin]);
+    ecs.destroy_entity(goblin).unwrap();
+    assert_eq!(ecs.related::<Targets>(archer), vec![]);
+    assert_eq!(ecs.related::<Owns>(goblin), vec![]);
+    assert_eq!(ecs.relations_to::<Targets>(goblin), vec![]);
+    assert_eq!(ecs.validate(), Ok(()));
+    assert_eq!(ecs.relate::<Owns>(archer, goblin), Err(EcsError::EntityNotFound(goblin)));
+}
+
+#[test]
+fn test_borrow_mut_many() {
+    use std::any::TypeId;
+    let mut ecs = Ecs::new();
+    let ids: Vec<_> = (0..4).map(|i| ecs.spawn((Score(i),))).collect();
+    let picked = [ids[3], ids[0], ids[2]];
+    for (i, score) in ecs.borrow_mut_many::<Score>(&picked).unwrap().into_iter().enumerate() {
+        score.0 += 10 * (i as u32 + 1);
+    }
+    let scores: Vec<_> = ids.iter().map(|id| ecs.get::<Score>(*id).unwrap().0).collect();
+    assert_eq!(scores, vec![20, 1, 32, 13]);
+    assert_eq!(ecs.borrow_mut_many::<Score>(&[]).unwrap().len(), 0);
+    assert_eq!(ecs.borrow_mut_many::<Score>(&[ids[1], ids[2], ids[1]]).err(),
+               Some(EcsError::AliasedEntity(ids[1])));
+    assert_eq!(ecs.borrow_mut_many::<Position>(&[ids[1]]).err(),
+               Some(EcsError::ComponentNotFound(TypeId::of::<Position>())));
+    ecs.destroy_entity(ids[2]).unwrap();
+    assert_eq!(ecs.borrow_mut_many::<Score>(&[ids[0], ids[2]]).err(),
+               Some(EcsError::EntityNotFound(ids[2])));
+}
+
+#[test]
+fn test_swap_with() {
+    let mut current = Ecs::new();
+    current.register_clone::<Score>();
+    let a = current.spawn((Score(1),));
+    let mut next = current.clone();
+    let _ = next.set(a, Score(2));
+    let b = next.spawn((Score(3),));
+    current.swap_with(&mut next);
+    assert_eq!(current.get::<Score>(a), Ok(Score(2)));
+    assert_eq!(current.get::<Score>(b), Ok(Score(3)));
+    assert_eq!(current.count::<Score>(), 2);
+    assert_eq!(next.get::<Score>(a), Ok(Score(1)));
+    assert!(!next.exists(b));
+    assert!(current.create_entity() != b);
+    assert_eq!(next.create_entity(), b);
+}
+
+#[test]
+fn test_apply_diff_prunes_free_slots() {
+    let mut later = Ecs::new();
+    for _ in 0..2 {
+        let id = later.create_entity();
+        later.destroy_entity(id).unwrap();
+    }
+    let revived = later.create_entity();
+    let mut world = Ecs::new();
+    let old = world.create_entity();
+    world.destroy_entity(old).unwrap();
+    world.apply_diff(&later.diff(&world)).unwrap();
+    assert!(world.exists(revived));
+    let fresh = world.create_entity();
+    assert_eq!(world.generation_of(fresh), Some(0));
+    assert_eq!(world.generation_of(revived), Some(2));
+    assert_eq!(world.validate(), Ok(()));
+    world.destroy_entity(revived).unwrap();
+    assert_eq!(world.status(revived), EntityStatus::Dead { current_generation: 2 });
+}
+
+#[test]
+#[should_panic(expected = "below 2^32")]
+fn test_id_offset_out_of_range() {
+    let _ = Ecs::with_id_offset(1 << 32);
+}
+
+#[test]
+fn test_no_reissued_ids() {
+    let mut ecs = Ecs::new();
+    let first = ecs.create_entity();
+    ecs.destroy_entity(first).unwrap();
+    let second = ecs.create_entity();
+    ecs.destroy_entity(second).unwrap();
+    ecs.get_or_create_at(first).unwrap();
+    ecs.destroy_entity(first).unwrap();
+    let weak = WeakEntity::new(second);
+    let third = ecs.create_entity();
+    assert!(third != second);
+    assert_eq!(weak.upgrade(&ecs), None);
+    assert_eq!(ecs.validate(), Ok(()));
+
+    let mut ecs = Ecs::new();
+    let first = ecs.create_entity();
+    let checkpoint = ecs.checkpoint();
+    ecs.destroy_entity(first).unwrap();
+    let second = ecs.create_entity();
+    ecs.rollback_to(&checkpoint).unwrap();
+    ecs.destroy_entity(first).unwrap();
+    let weak = WeakEntity::new(second);
+    let third = ecs.create_entity();
+    assert!(third != second);
+    assert_eq!(weak.upgrade(&ecs), None);
+    assert_eq!(ecs.validate(), Ok(()));
+}
+
+#[test]
+#[should_panic(expected = "shares its index")]
+fn test_from_parts_rejects_shared_index() {
+    let mut ecs = Ecs::new();
+    let old = ecs.create_entity();
+    ecs.destroy_entity(old).unwrap();
+    let new = ecs.create_entity();
+    let _ = Ecs::from_parts(vec![(old, ComponentMap::new()), (new, ComponentMap::new())], 1);
+}
+
+#[test]
+fn test_rollback_keeps_order_and_observers_quiet() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    let hits = Rc::new(Cell::new(0));
+    let mut ecs = Ecs::new_ordered();
+    {
+        let hits = hits.clone();
+        ecs.on_destroy(move |_| hits.set(hits.get() + 1));
+    }
+    let a = ecs.create_entity();
+    let b = ecs.create_entity();
+    ecs.enable_event_log();
+    let checkpoint = ecs.checkpoint();
+    ecs.destroy_entity(a).unwrap();
+    let _ = ecs.create_entity();
+    assert_eq!(hits.get(), 1);
+    let _ = ecs.drain_events();
+    ecs.rollback_to(&checkpoint).unwrap();
+    assert_eq!(ecs.entity_ids(), Some(&[a, b][..]));
+    assert_eq!(hits.get(), 1);
+    assert_eq!(ecs.drain_events(), vec![]);
+    assert_eq!(ecs.validate(), Ok(()));
+}